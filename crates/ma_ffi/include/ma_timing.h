@@ -1,8 +1,9 @@
 #include <cstdint>
 namespace ma_timing {
 
-struct Timer {
-	uint8_t data[192];
+// Size and alignment of the Rust Timer, checked in ma_timing/src/ffi.rs
+struct alignas(8) Timer {
+	uint8_t data[480];
 };
 extern "C" {
 	void create_timer(const char* name, Timer* timer);
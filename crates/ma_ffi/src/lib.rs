@@ -5,12 +5,13 @@ use ma_time::Instant;
 #[inline(always)]
 pub extern "C" fn create_timer(
     name: *const std::os::raw::c_char,
-    timer: &mut Timer
+    timer: *mut Timer
 )
 {
     let p = unsafe{ std::ffi::CStr::from_ptr(name)}.to_str().unwrap();
     let t = Timer::new(p);
-    *timer = t;
+    // The memory behind `timer` is uninitialized, so no reference to it and don't drop it
+    unsafe { std::ptr::write(timer, t) };
 }
 
 #[no_mangle]
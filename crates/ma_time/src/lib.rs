@@ -37,6 +37,21 @@ fn rdtscp() -> u64 {
     }
 }
 
+/// rdtscp together with the TSC_AUX register, which Linux sets to `node << 12 | cpu`
+fn rdtscp_aux() -> (u64, u32) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::arch::x86_64::__rdtscp;
+        let mut aux = 0u32;
+        let t = unsafe { __rdtscp(&mut aux as *mut _) };
+        (t, aux)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        (global_clock().raw(), 0)
+    }
+}
+
 
 // Everything is rdtsc brother
 #[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
@@ -50,6 +65,11 @@ impl Instant {
     pub fn now() -> Self {
         Instant(rdtscp())
     }
+    /// Like [`now`](Instant::now), also returning the id of the cpu the clock was read on.
+    pub fn now_and_cpu() -> (Self, u32) {
+        let (t, aux) = rdtscp_aux();
+        (Instant(t), aux & 0xfff)
+    }
     pub fn elapsed(&self) -> Nanos {
         Nanos(global_clock().delta_as_nanos(self.0, rdtscp()))
     }
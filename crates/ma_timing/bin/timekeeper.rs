@@ -1,10 +1,17 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
-use std::time::Duration;
-use ma_timing::TimeKeeper;
+use std::{net::SocketAddr, time::Duration};
+use ma_timing::{
+    engine::MAX_SIGNIFICANT_DIGITS,
+    prometheus::MetricsServer,
+    recorder::Recorder,
+    sink::{Rotation, SinkFormat, SnapshotSink},
+    timekeeper::{Bounds, HeadlessOutput},
+    TimeKeeper,
+};
 
 use std::io::stdout;
 #[derive(Parser, Debug, Clone)]
@@ -15,9 +22,144 @@ pub struct Configuration {
     #[arg(long, default_value_t = 256)]
     n_datapoints: usize,
 
+    /// Precision of the reported percentiles, in decimal digits. Every timer needs about 230 KB
+    /// at 2 digits and 1.8 MB at 3
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..=MAX_SIGNIFICANT_DIGITS as i64))]
+    significant_digits: u8,
+
     /// in secs
     #[arg(long, default_value_t = 0.5)]
     report_interval: f32,
+
+    /// Remove the queues of producers that are no longer running before starting
+    #[arg(long)]
+    gc: bool,
+
+    /// Only track messages whose payload field has this value, e.g. `--filter venue=3`.
+    /// Press `g` to group the selected timer by its payload fields
+    #[arg(long = "filter", value_parser = parse_filter)]
+    filters: Vec<(String, String)>,
+
+    /// Only track measurements of a timer, group or pipeline within bounds in nanos, e.g.
+    /// `--bounds gateway.decode=100..50000` or `--bounds gateway=..auto:99.9` to reject those
+    /// above ten times that percentile. Press `b` to toggle the auto bound of the selected timer
+    #[arg(long = "bounds", value_parser = parse_bounds)]
+    bounds: Vec<(String, Bounds)>,
+
+    /// Bounds of the timers without their own, e.g. `--default-bounds 1..auto`
+    #[arg(long)]
+    default_bounds: Option<Bounds>,
+
+    /// Print a table of all timers every report interval instead of showing the terminal UI,
+    /// for running without a tty
+    #[arg(long)]
+    headless: bool,
+
+    /// Exit after this many secs, in headless mode
+    #[arg(long, requires = "headless")]
+    duration: Option<f32>,
+
+    /// Also log to this file. In headless mode the tables are logged rather than printed
+    #[arg(long)]
+    log_file: Option<String>,
+
+    /// Append a row per datapoint of each timer to this CSV file, written every report interval
+    #[arg(long)]
+    csv: Option<String>,
+
+    /// Same as `--csv` with one JSON object per line
+    #[arg(long)]
+    jsonl: Option<String>,
+
+    /// Move the CSV and JSONL files aside once they reach this size, e.g. `100M`
+    #[arg(long, value_parser = parse_size)]
+    rotate_size: Option<u64>,
+
+    /// Move the CSV and JSONL files aside after this many secs
+    #[arg(long)]
+    rotate_interval: Option<f32>,
+
+    /// Serve Prometheus metrics at `/metrics` on this address, e.g. `127.0.0.1:9464`
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Write every message of the chosen timers to a binary capture file instead of reporting them,
+    /// logging the number of recorded and dropped messages every report interval
+    Record {
+        /// Record this timer, with all its producers if it's shared. Records every timer if not given
+        #[arg(long = "timer")]
+        timers: Vec<String>,
+
+        #[arg(long, short)]
+        output: String,
+
+        /// Exit after this many secs
+        #[arg(long)]
+        duration: Option<f32>,
+    },
+}
+
+fn parse_filter(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+     .map(|(field, value)| (field.to_string(), value.to_string()))
+     .ok_or_else(|| format!("expected field=value, got {s}"))
+}
+
+fn parse_bounds(s: &str) -> Result<(String, Bounds), String> {
+    let (name, bounds) = s.split_once('=').ok_or_else(|| format!("expected name=MIN..MAX, got {s}"))?;
+    Ok((name.to_string(), bounds.parse()?))
+}
+
+/// Bytes, with an optional `K`, `M` or `G` suffix
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+    let unit = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("expected a size like 100M, got {s}")),
+    };
+    digits.parse::<u64>().map(|n| n * unit).map_err(|e| format!("invalid size {s}: {e}"))
+}
+
+fn record(
+    mut recorder: Recorder,
+    core: core_affinity::CoreId,
+    report_interval: Duration,
+    duration: Option<Duration>,
+) {
+    core_affinity::set_for_current(core);
+    let start = std::time::Instant::now();
+    let mut dropped = 0;
+    loop {
+        recorder.poll_producers().expect("couldn't scan queue directory");
+        let report = std::time::Instant::now();
+        while report.elapsed() < report_interval {
+            recorder.record().expect("couldn't write capture");
+        }
+        recorder.flush().expect("couldn't write capture");
+        log::info!("recorded {} messages, {} dropped", recorder.n_messages(), recorder.dropped());
+        if recorder.dropped() > dropped {
+            dropped = recorder.dropped();
+            for s in recorder.streams().filter(|s| s.dropped > 0) {
+                log::warn!("{} {:?}: dropped {} of {}", s.queue_name, s.channel, s.dropped, s.n_messages + s.dropped);
+            }
+        }
+        if duration.is_some_and(|d| start.elapsed() >= d) {
+            break;
+        }
+    }
+    recorder.finish().expect("couldn't write capture");
 }
 
 pub fn setup_logging(log_file: Option<&str>) {
@@ -39,18 +181,62 @@ pub fn setup_logging(log_file: Option<&str>) {
     t.apply().unwrap();
 }
 fn main() {
-    stdout().execute(EnterAlternateScreen).unwrap();
-    enable_raw_mode().unwrap();
+    let config = Configuration::parse();
+    if let Some(Command::Record { timers, output, duration }) = config.command {
+        setup_logging(config.log_file.as_deref());
+        let recorder = Recorder::create(&output, timers).expect("couldn't create capture file");
+        record(
+            recorder,
+            *core_affinity::get_core_ids().unwrap().last().unwrap(),
+            Duration::from_secs_f32(config.report_interval),
+            duration.map(Duration::from_secs_f32),
+        );
+        return;
+    }
+    if !config.headless {
+        stdout().execute(EnterAlternateScreen).unwrap();
+        enable_raw_mode().unwrap();
+    }
 
-    setup_logging(None);
+    setup_logging(config.log_file.as_deref());
 
-    let config = Configuration::parse();
+    if config.gc {
+        match ma_timing::gc() {
+            Ok(removed) => log::info!("removed stale queues of {removed:?}"),
+            Err(e) => log::error!("couldn't clean up stale queues: {e}"),
+        }
+    }
     let mut tc = TimeKeeper::new(
         *core_affinity::get_core_ids().unwrap().last().unwrap(),
         Duration::from_secs_f32(config.report_interval),
         config.samples_per_datapoint,
         config.n_datapoints,
     );
+    tc.set_filters(config.filters);
+    tc.set_significant_digits(config.significant_digits);
+    if let Some(bounds) = config.default_bounds {
+        tc.set_default_bounds(bounds);
+    }
+    for (name, bounds) in config.bounds {
+        tc.set_bounds(name, bounds);
+    }
+    let rotation = Rotation {
+        max_size: config.rotate_size,
+        max_age: config.rotate_interval.map(Duration::from_secs_f32),
+    };
+    for (path, format) in [(config.csv, SinkFormat::Csv), (config.jsonl, SinkFormat::Jsonl)] {
+        if let Some(path) = path {
+            tc.add_sink(SnapshotSink::new(path, format, rotation).expect("couldn't open snapshot file"));
+        }
+    }
+    if let Some(addr) = config.metrics_addr {
+        tc.set_metrics_server(MetricsServer::bind(addr).expect("couldn't bind metrics address"));
+    }
+    if config.headless {
+        let output = if config.log_file.is_some() { HeadlessOutput::Log } else { HeadlessOutput::Stdout };
+        tc.execute_headless(output, config.duration.map(Duration::from_secs_f32));
+        return;
+    }
     tc.execute();
     stdout().execute(LeaveAlternateScreen).unwrap();
     disable_raw_mode().unwrap();
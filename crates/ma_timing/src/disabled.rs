@@ -0,0 +1,248 @@
+//! No-op stand-ins for everything that produces timing messages, swapped in by the
+//! `disabled` feature. They keep the same API but compile down to nothing and never
+//! touch shared memory.
+use std::{fmt::Display, marker::PhantomData};
+
+use ma_time::{Duration, Instant};
+
+use crate::{messages::TimingMessage, Payload, Sampling};
+
+#[repr(C)]
+pub struct Timer<P: Payload = ()> {
+    pub curmsg: TimingMessage,
+    _payload:   PhantomData<P>,
+}
+
+unsafe impl<P: Payload> Send for Timer<P> {}
+unsafe impl<P: Payload> Sync for Timer<P> {}
+
+impl Timer {
+    #[inline(always)]
+    pub fn new<S: Display>(name: S) -> Self {
+        Self::with_payload(name, ())
+    }
+}
+
+impl<P: Payload> Timer<P> {
+    #[inline(always)]
+    pub fn with_payload<S: Display>(_name: S, _payload: P) -> Self {
+        Self { curmsg: Default::default(), _payload: PhantomData }
+    }
+    #[inline(always)]
+    pub fn set_payload(&mut self, _payload: P) {}
+    #[inline(always)]
+    pub fn set_outlier_threshold(&mut self, _threshold: Duration) {}
+    #[inline(always)]
+    pub fn set_tag(&mut self, _tag: u64) {}
+    #[cfg(feature = "perf")]
+    #[inline(always)]
+    pub fn enable_perf_counters(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+    #[inline(always)]
+    pub fn set_sampling(&mut self, _sampling: Sampling) {}
+    #[inline(always)]
+    pub fn name(&self) -> &str {
+        ""
+    }
+    #[inline(always)]
+    pub fn set_unlink_on_drop(&mut self, _unlink: bool) {}
+    #[inline(always)]
+    pub fn start(&mut self) {}
+    #[inline(always)]
+    pub fn start_t(&self) -> &Instant {
+        &self.curmsg.start_t
+    }
+    #[inline(always)]
+    pub fn stop_t(&self) -> &Instant {
+        &self.curmsg.stop_t
+    }
+    #[inline(always)]
+    pub fn stop(&mut self) {}
+    #[inline(always)]
+    pub fn stop_and_latency(&mut self, _ingestion_t: Instant) {}
+    #[inline(always)]
+    pub fn set_stop(&mut self, _stop: Instant) {}
+    #[inline(always)]
+    pub fn set_start(&mut self, _start: Instant) {}
+    #[inline(always)]
+    pub fn latency(&mut self, _ingestion_t: Instant) {}
+    #[inline(always)]
+    pub fn record(&mut self, _ingestion_t: Instant, _busy: Duration) {}
+    #[inline(always)]
+    pub fn send_latency(&mut self) {}
+    #[inline(always)]
+    pub fn send_business(&mut self) {}
+}
+
+pub mod pipeline {
+    use std::fmt::Display;
+
+    use ma_time::Instant;
+
+    use crate::messages::PipelineMessage;
+
+    #[repr(C)]
+    pub struct PipelineTimer {
+        pub curmsg: PipelineMessage,
+        n_stages:   usize,
+    }
+
+    impl PipelineTimer {
+        #[inline(always)]
+        pub fn new<S: Display>(_name: S, stages: &[&str]) -> Self {
+            Self { curmsg: Default::default(), n_stages: stages.len() }
+        }
+        #[inline(always)]
+        pub fn n_stages(&self) -> usize {
+            self.n_stages
+        }
+        #[inline(always)]
+        pub fn start(&mut self) {}
+        #[inline(always)]
+        pub fn start_at(&mut self, _ingestion_t: Instant) {}
+        #[inline(always)]
+        pub fn checkpoint(&mut self, _stage: usize) {}
+        #[inline(always)]
+        pub fn set_checkpoint(&mut self, _stage: usize, _t: Instant) {}
+        #[inline(always)]
+        pub fn send(&mut self) {}
+    }
+}
+
+pub mod registry {
+    use std::marker::PhantomData;
+
+    use ma_time::{Duration, Instant};
+
+    use crate::Sampling;
+
+    pub fn thread_queue_name(name: &str, id: usize) -> String {
+        format!("{name}#{}.{id}", std::process::id())
+    }
+
+    /// Neither `Send` nor `Sync`, like the real one
+    #[derive(Clone, Copy, Debug)]
+    pub struct TimerHandle(PhantomData<*mut ()>);
+
+    impl TimerHandle {
+        #[inline(always)]
+        pub fn start(self) {}
+        #[inline(always)]
+        pub fn stop(self) {}
+        #[inline(always)]
+        pub fn stop_and_latency(self, _ingestion_t: Instant) {}
+        #[inline(always)]
+        pub fn latency(self, _ingestion_t: Instant) {}
+        #[inline(always)]
+        pub fn record(self, _ingestion_t: Instant, _busy: Duration) {}
+        #[inline(always)]
+        pub fn set_sampling(self, _sampling: Sampling) {}
+        #[inline(always)]
+        pub fn set_outlier_threshold(self, _threshold: Duration) {}
+        #[inline(always)]
+        pub fn set_tag(self, _tag: u64) {}
+    }
+
+    #[inline(always)]
+    pub fn local(_name: &str) -> TimerHandle {
+        TimerHandle(PhantomData)
+    }
+}
+
+pub mod shared {
+    use ma_time::{Duration, Instant};
+
+    use crate::registry::TimerHandle;
+
+    #[derive(Clone, Debug)]
+    pub struct SharedTimer;
+
+    impl SharedTimer {
+        #[inline(always)]
+        pub fn new<S: ToString>(_name: S) -> Self {
+            Self
+        }
+        #[inline(always)]
+        pub fn name(&self) -> &str {
+            ""
+        }
+        #[inline(always)]
+        pub fn local(&self) -> TimerHandle {
+            crate::registry::local("")
+        }
+        #[inline(always)]
+        pub fn start(&self) {}
+        #[inline(always)]
+        pub fn stop(&self) {}
+        #[inline(always)]
+        pub fn stop_and_latency(&self, _ingestion_t: Instant) {}
+        #[inline(always)]
+        pub fn latency(&self, _ingestion_t: Instant) {}
+        #[inline(always)]
+        pub fn record(&self, _ingestion_t: Instant, _busy: Duration) {}
+    }
+}
+
+pub mod future {
+    use std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use crate::SharedTimer;
+
+    /// Timed futures are just the futures themselves
+    #[derive(Debug)]
+    #[repr(transparent)]
+    pub struct TimedFuture<F>(F);
+
+    impl<F: Future> TimedFuture<F> {
+        #[inline(always)]
+        pub fn new(inner: F, _timer: SharedTimer) -> Self {
+            Self(inner)
+        }
+    }
+
+    impl<F: Future> Future for TimedFuture<F> {
+        type Output = F::Output;
+
+        #[inline(always)]
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            // Safety: the inner future is never moved out of `self`
+            unsafe { self.map_unchecked_mut(|f| &mut f.0) }.poll(cx)
+        }
+    }
+
+    pub trait TimedExt: Future + Sized {
+        #[inline(always)]
+        fn timed(self, _timer: &SharedTimer) -> TimedFuture<Self> {
+            TimedFuture(self)
+        }
+    }
+
+    impl<F: Future> TimedExt for F {}
+}
+
+#[cfg(feature = "tracing")]
+pub mod tracing {
+    use ::tracing::{Metadata, Subscriber};
+    use tracing_subscriber::Layer;
+
+    #[derive(Clone, Default)]
+    pub struct TimingLayer;
+
+    impl TimingLayer {
+        pub fn new() -> Self {
+            Self
+        }
+        pub fn with_span_filter<F>(self, _filter: F) -> Self
+            where F: Fn(&Metadata<'_>) -> bool + Send + Sync + 'static
+        {
+            self
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for TimingLayer {}
+}
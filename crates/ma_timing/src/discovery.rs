@@ -0,0 +1,253 @@
+//! Finds the producers in [`QUEUE_DIR`], so the timekeeper and other tools don't have to
+//! know how their files are named.
+//!
+//! ```no_run
+//! let mut watcher = ma_timing::discovery::watch_timers().expect("couldn't watch queue directory");
+//! loop {
+//!     for event in watcher.poll().expect("couldn't scan queue directory") {
+//!         println!("{event:?}");
+//!     }
+//!     std::thread::sleep(std::time::Duration::from_millis(100));
+//! }
+//! ```
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    header::{HeaderError, HeaderKind, QueueHeader},
+    lifecycle::PREFIXES,
+    QUEUE_DIR,
+};
+
+/// A producer, i.e. a [`Timer`](crate::Timer) or [`PipelineTimer`](crate::PipelineTimer)
+#[derive(Debug)]
+pub struct TimerInfo {
+    /// Name the producer was created with, `name#pid.id` for the per-thread producers of a
+    /// [`SharedTimer`](crate::SharedTimer)
+    pub queue_name: String,
+    pub kind:       HeaderKind,
+    /// All files of the producer in [`QUEUE_DIR`]
+    pub queues:     Vec<PathBuf>,
+    pub header:     Result<QueueHeader, HeaderError>,
+}
+
+impl TimerInfo {
+    /// Name of the logical timer, shared by all its producers
+    pub fn name(&self) -> &str {
+        self.queue_name.split('#').next().unwrap_or(&self.queue_name)
+    }
+
+    /// Path of the file with `prefix`, e.g. `"outliers-"`, if the producer created it
+    pub fn queue(&self, prefix: &str) -> Option<&Path> {
+        let file_name = format!("{prefix}{}", self.queue_name);
+        self.queues.iter().map(PathBuf::as_path).find(|q| q.file_name().is_some_and(|n| *n == *file_name))
+    }
+}
+
+/// Lists the producers currently in [`QUEUE_DIR`], including the ones that haven't
+/// written their header yet.
+pub fn list_timers() -> std::io::Result<Vec<TimerInfo>> {
+    let mut files: Vec<String> =
+        std::fs::read_dir(QUEUE_DIR)?.filter_map(|e| e.ok()).filter_map(|e| e.file_name().into_string().ok()).collect();
+    files.sort_unstable();
+    let mut timers = Vec::new();
+    for file in &files {
+        let (queue_name, kind) = if let Some(queue_name) = file.strip_prefix("latency-") {
+            (queue_name, HeaderKind::Timer)
+        } else if let Some(queue_name) = file.strip_prefix("pipeline-") {
+            (queue_name, HeaderKind::Pipeline)
+        } else {
+            continue;
+        };
+        let queues = PREFIXES.iter()
+                             .map(|prefix| format!("{prefix}{queue_name}"))
+                             .filter(|f| files.binary_search(f).is_ok())
+                             .map(|f| Path::new(QUEUE_DIR).join(f))
+                             .collect();
+        timers.push(TimerInfo { queue_name: queue_name.to_string(),
+                                kind,
+                                queues,
+                                header: QueueHeader::read(queue_name) });
+    }
+    Ok(timers)
+}
+
+#[derive(Debug)]
+pub enum TimerEvent {
+    /// A producer appeared and its header can be read
+    Added(TimerInfo),
+    /// A known producer created more files, e.g. when it enabled outliers or perf counters
+    Updated(TimerInfo),
+    /// The queues of the producer with this queue name were unlinked
+    Removed(String),
+}
+
+/// Reports changes to the producers in [`QUEUE_DIR`], see [`watch_timers`].
+pub struct TimerWatcher {
+    #[cfg(target_os = "linux")]
+    inotify: inotify::Inotify,
+    // Creation time and files of the producers reported as added, by queue name
+    known:   HashMap<String, (u64, Vec<PathBuf>)>,
+    // Producers whose header couldn't be read at the last scan
+    pending: Vec<(String, HeaderError)>,
+    scanned: bool,
+}
+
+/// Watches [`QUEUE_DIR`] for producers coming and going, using inotify on Linux
+/// and rescanning on every [`poll`](TimerWatcher::poll) elsewhere.
+pub fn watch_timers() -> std::io::Result<TimerWatcher> {
+    Ok(TimerWatcher { #[cfg(target_os = "linux")]
+                      inotify: inotify::Inotify::new(QUEUE_DIR)?,
+                      known: HashMap::new(),
+                      pending: Vec::new(),
+                      scanned: false })
+}
+
+impl TimerWatcher {
+    /// Returns what changed since the last call without blocking, all current producers on the first one.
+    /// A producer that restarted under the same name is removed and added again.
+    pub fn poll(&mut self) -> std::io::Result<Vec<TimerEvent>> {
+        if self.scanned && self.pending.is_empty() && !self.changed()? {
+            return Ok(Vec::new());
+        }
+        self.scanned = true;
+        self.pending.clear();
+        let timers = list_timers()?;
+
+        let mut events = Vec::new();
+        self.known.retain(|queue_name, _| {
+                      let alive = timers.iter().any(|t| t.queue_name == *queue_name);
+                      if !alive {
+                          events.push(TimerEvent::Removed(queue_name.clone()));
+                      }
+                      alive
+                  });
+        for timer in timers {
+            let created = match &timer.header {
+                Ok(header) => header.created,
+                Err(_) => {
+                    if !self.known.contains_key(&timer.queue_name) {
+                        // Producers write their header right after their queues, try again next time
+                        let TimerInfo { queue_name, header, .. } = timer;
+                        self.pending.push((queue_name, header.unwrap_err()));
+                    }
+                    continue;
+                }
+            };
+            match self.known.get_mut(&timer.queue_name) {
+                None => {
+                    self.known.insert(timer.queue_name.clone(), (created, timer.queues.clone()));
+                    events.push(TimerEvent::Added(timer));
+                }
+                Some((known_created, _)) if *known_created != created => {
+                    events.push(TimerEvent::Removed(timer.queue_name.clone()));
+                    self.known.insert(timer.queue_name.clone(), (created, timer.queues.clone()));
+                    events.push(TimerEvent::Added(timer));
+                }
+                Some((_, queues)) if *queues != timer.queues => {
+                    *queues = timer.queues.clone();
+                    events.push(TimerEvent::Updated(timer));
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(events)
+    }
+
+    /// Producers whose header couldn't be read at the last [`poll`](TimerWatcher::poll), and why.
+    /// They are added as soon as it can.
+    pub fn pending(&self) -> &[(String, HeaderError)] {
+        &self.pending
+    }
+
+    #[cfg(target_os = "linux")]
+    fn changed(&self) -> std::io::Result<bool> {
+        self.inotify.changed()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn changed(&self) -> std::io::Result<bool> {
+        Ok(true)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::{
+        ffi::CString,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    };
+
+    pub(super) struct Inotify(OwnedFd);
+
+    impl Inotify {
+        pub(super) fn new(dir: &str) -> std::io::Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            let dir = CString::new(dir)?;
+            let mask = libc::IN_CREATE | libc::IN_CLOSE_WRITE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO;
+            if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), mask) } < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(Self(fd))
+        }
+
+        /// Drains the pending events, returning whether there were any.
+        /// Which files changed doesn't matter, the watcher rescans the directory.
+        pub(super) fn changed(&self) -> std::io::Result<bool> {
+            let mut buf = [0u8; 4096];
+            let mut changed = false;
+            loop {
+                let n = unsafe { libc::read(self.0.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                if n > 0 {
+                    changed = true;
+                    continue;
+                }
+                let err = std::io::Error::last_os_error();
+                return match err.kind() {
+                    std::io::ErrorKind::WouldBlock => Ok(changed),
+                    _ => Err(err),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "disabled")))]
+mod tests {
+    use super::*;
+
+    // Other tests create producers concurrently, only look at ours
+    fn ours(watcher: &mut TimerWatcher) -> Vec<TimerEvent> {
+        let is_ours = |n: &str| n == "discovery-test";
+        watcher.poll()
+               .unwrap()
+               .into_iter()
+               .filter(|e| match e {
+                   TimerEvent::Added(t) | TimerEvent::Updated(t) => is_ours(&t.queue_name),
+                   TimerEvent::Removed(n) => is_ours(n),
+               })
+               .collect()
+    }
+
+    #[test]
+    fn watches_producers() {
+        let mut watcher = watch_timers().unwrap();
+        let mut timer = crate::Timer::new("discovery-test");
+        let events = ours(&mut watcher);
+        assert!(matches!(&events[..], [TimerEvent::Added(t)] if t.name() == "discovery-test" && t.header.is_ok()));
+
+        timer.set_outlier_threshold(ma_time::Duration(1));
+        let events = ours(&mut watcher);
+        assert!(matches!(&events[..], [TimerEvent::Updated(t)] if t.queue("outliers-").is_some()));
+
+        crate::lifecycle::unlink("discovery-test");
+        let events = ours(&mut watcher);
+        assert!(matches!(&events[..], [TimerEvent::Removed(n)] if n == "discovery-test"));
+    }
+}
@@ -0,0 +1,1220 @@
+//! Statistics of the timers and pipelines producing to the queue directory, without any UI.
+//!
+//! A [`StatsEngine`] consumes their queues and produces a [`TimerSnapshot`] of each on demand,
+//! e.g. to embed in a monitoring daemon. The [`TimeKeeper`](crate::TimeKeeper) TUI is built on it.
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    str::FromStr,
+};
+
+use ma_queues::{Consumer, ReadError};
+use ma_time::*;
+
+use crate::{
+    discovery::{self, TimerEvent, TimerInfo, TimerWatcher},
+    header::{HeaderKind, QueueHeader},
+    histogram::Histogram,
+    messages::{OutlierMessage, PerfMessage, PipelineMessage, TimingMessage},
+    payload,
+    stats::{Dispersion, Snapshot},
+};
+
+/// Measurements above this many times the percentile of an auto bound are rejected
+const AUTO_FACTOR: u64 = 10;
+const DEFAULT_AUTO_PERCENTILE: f64 = 99.9;
+/// Most outliers of a timer kept between two snapshots, the latest ones
+const MAX_OUTLIERS: usize = 64;
+/// Most precise the timekeeper keeps its histograms, see [`StatsEngine::set_significant_digits`]
+pub const MAX_SIGNIFICANT_DIGITS: u8 = 3;
+
+/// Which measurements a timer accepts, the others are counted as rejected rather than tracked.
+/// Guards the statistics against bogus values, e.g. from uninitialized start times.
+///
+/// Parsed from `MIN..MAX` in nanos where either side can be left out, and `MAX` can be `auto`
+/// or `auto:PERCENTILE`, e.g. `100..50000` or `..auto:99`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub min:  Option<Nanos>,
+    pub max:  Option<Nanos>,
+    /// Rejects measurements above [`AUTO_FACTOR`] times this percentile of the accepted ones,
+    /// once there are any
+    pub auto: Option<f64>,
+}
+
+impl Bounds {
+    pub fn is_unbounded(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn toggle_auto(&mut self) {
+        self.auto = match self.auto {
+            Some(_) => None,
+            None => Some(DEFAULT_AUTO_PERCENTILE),
+        };
+    }
+}
+
+impl FromStr for Bounds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s.split_once("..").ok_or_else(|| format!("expected MIN..MAX, got {s}"))?;
+        let nanos = |v: &str| v.parse::<Nanos>().map_err(|e| format!("invalid bound {v}: {e}"));
+        let mut bounds = Self::default();
+        if !min.is_empty() {
+            bounds.min = Some(nanos(min)?);
+        }
+        if let Some(auto) = max.strip_prefix("auto") {
+            bounds.auto = match auto.strip_prefix(':') {
+                Some(p) => Some(p.parse::<f64>()
+                                 .ok()
+                                 .filter(|p| (0.0..=100.0).contains(p))
+                                 .ok_or_else(|| format!("invalid percentile {p}"))?),
+                None if auto.is_empty() => Some(DEFAULT_AUTO_PERCENTILE),
+                None => return Err(format!("invalid bound {max}")),
+            };
+        } else if !max.is_empty() {
+            bounds.max = Some(nanos(max)?);
+        }
+        Ok(bounds)
+    }
+}
+
+impl Display for Bounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(min) = self.min {
+            write!(f, "{min}")?;
+        }
+        write!(f, "..")?;
+        match (self.max, self.auto) {
+            (Some(max), _) => write!(f, "{max}"),
+            (None, Some(p)) => write!(f, "auto:{p}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+//TODO: Have tuple of 2 timingdatas pls
+/// Keep track of msg latencies
+/// All in nanos
+#[derive(Debug, Clone)]
+pub struct TimingData {
+    // Measurements of the current datapoint, and of all of them including the current one
+    datapoint:             Histogram,
+    cumulative:            Histogram,
+    // Standard deviation and jitter of the current datapoint and cumulatively, like the histograms
+    datapoint_dispersion:  Dispersion,
+    cumulative_dispersion: Dispersion,
+    // Statistics of the last complete datapoint
+    last:                  Snapshot,
+    n_registered:          u64,
+    // Complete datapoints not taken by a snapshot yet, at most `n_datapoints` of the latest
+    completed:             Vec<Datapoint>,
+    n_datapoints:          usize,
+
+    bounds:         Bounds,
+    // `bounds` in ticks, the auto bound is updated with every datapoint
+    min_accepted:   Duration,
+    max_accepted:   Duration,
+    rejected_below: usize,
+    rejected_above: usize,
+
+    // Included in every measurement according to the producer's header, zero where it doesn't
+    // describe the measurements, see `TimingSnapshot::corrected`
+    clock_overhead: Duration,
+
+    samples_per_datapoint: usize,
+    n_messages:            usize,
+    // Calls the messages stand for when the producer samples
+    n_calls:               u64,
+    last_report:           Instant,
+
+    // Messages we missed because the producer sped past us
+    dropped: usize,
+}
+
+impl TimingData {
+    pub fn new(samples_per_datapoint: usize, n_datapoints: usize, clock_overhead: Duration, significant_digits: u8) -> Self {
+        Self { datapoint: Histogram::new(significant_digits),
+               cumulative: Histogram::new(significant_digits),
+               datapoint_dispersion: Dispersion::default(),
+               cumulative_dispersion: Dispersion::default(),
+               last: Snapshot::default(),
+               n_registered: 0,
+               completed: Vec::new(),
+               n_datapoints: n_datapoints.max(1),
+               bounds: Bounds::default(),
+               min_accepted: Duration::ZERO,
+               max_accepted: Duration::MAX,
+               rejected_below: 0,
+               rejected_above: 0,
+               clock_overhead,
+               samples_per_datapoint,
+               n_messages: 0,
+               n_calls: 0,
+               last_report: Instant::now(),
+               dropped: 0 }
+    }
+
+    /// E.g. the start/stop overhead measured by the producer, see [`Overhead`](crate::header::Overhead)
+    pub fn set_clock_overhead(&mut self, clock_overhead: Duration) {
+        self.clock_overhead = clock_overhead;
+    }
+
+    pub fn set_bounds(&mut self, bounds: Bounds) {
+        self.bounds = bounds;
+        self.min_accepted = bounds.min.map_or(Duration::ZERO, Duration::from);
+        self.update_max_accepted();
+    }
+
+    fn update_max_accepted(&mut self) {
+        let max = self.bounds.max.map_or(Duration::MAX, Duration::from);
+        let auto = match self.bounds.auto {
+            Some(p) if !self.cumulative.is_empty() => {
+                Duration(self.cumulative.percentile(p).0.saturating_mul(AUTO_FACTOR))
+            }
+            _ => Duration::MAX,
+        };
+        self.max_accepted = max.min(auto);
+    }
+
+    /// Whether `el` is within the bounds, counting it as rejected otherwise
+    fn accept(&mut self, el: Duration) -> bool {
+        if el < self.min_accepted {
+            self.rejected_below += 1;
+            false
+        } else if el > self.max_accepted {
+            self.rejected_above += 1;
+            false
+        } else {
+            true
+        }
+    }
+
+    fn register_datapoint(&mut self) {
+        if self.datapoint.is_empty() {
+            return;
+        }
+        self.last = Snapshot::from_histogram(&self.datapoint, &self.datapoint_dispersion);
+        self.n_registered += 1;
+        if self.completed.len() == self.n_datapoints {
+            self.completed.remove(0);
+        }
+        self.completed.push(Datapoint { completed: Nanos::now(), stats: self.last });
+        self.datapoint.clear();
+        self.datapoint_dispersion = Dispersion::default();
+        if self.bounds.auto.is_some() {
+            self.update_max_accepted();
+        }
+    }
+
+    /// Counts the calls `msg` stands for, also when it's rejected, and tracks it within the bounds.
+    /// Returns whether it completed a datapoint.
+    fn track(&mut self, msg: &TimingMessage) -> bool {
+        self.n_calls += msg.weight.max(1);
+        self.track_elapsed(msg.elapsed())
+    }
+
+    pub(crate) fn track_elapsed(&mut self, el: Duration) -> bool {
+        self.accept(el) && self.track_accepted(el)
+    }
+
+    /// Like [`track_elapsed`](TimingData::track_elapsed) for a measurement that passed [`accept`](TimingData::accept)
+    fn track_accepted(&mut self, el: Duration) -> bool {
+        self.n_messages += 1;
+        self.datapoint.record(el);
+        self.datapoint_dispersion.track(el);
+        self.cumulative.record(el);
+        self.cumulative_dispersion.track(el);
+        if self.datapoint.len() as usize == self.samples_per_datapoint {
+            self.register_datapoint();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Statistics up to now, taking the datapoints completed since the previous snapshot.
+    /// The throughput is that since the previous snapshot.
+    pub fn snapshot(&mut self) -> TimingSnapshot {
+        // Measurements without a message, e.g. pipeline stages, stand for one call each
+        let n_calls = if self.n_calls == 0 { self.n_messages as u64 } else { self.n_calls };
+        let snapshot = TimingSnapshot { last:           self.last,
+                                        current:        Snapshot::from_histogram(&self.datapoint, &self.datapoint_dispersion),
+                                        new_datapoints: std::mem::take(&mut self.completed),
+                                        cumulative:     Snapshot::from_histogram(&self.cumulative, &self.cumulative_dispersion),
+                                        sum:            self.cumulative.sum(),
+                                        clock_overhead: self.clock_overhead,
+                                        n_messages:     self.n_messages,
+                                        throughput:     n_calls as f64 / self.last_report.elapsed().as_secs(),
+                                        sampled:        if n_calls == 0 {
+                                                            1.0
+                                                        } else {
+                                                            self.n_messages as f64 / n_calls as f64
+                                                        },
+                                        datapoints:     self.n_registered,
+                                        dropped:        self.dropped,
+                                        bounds:         self.bounds,
+                                        max_accepted:   self.max_accepted,
+                                        rejected_below: self.rejected_below,
+                                        rejected_above: self.rejected_above, };
+        self.n_messages = 0;
+        self.n_calls = 0;
+        self.last_report = Instant::now();
+        snapshot
+    }
+}
+
+/// Statistics of the latency or business measurements of a timer as they were measured,
+/// see [`corrected`](TimingSnapshot::corrected) for those without the clock overhead
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TimingSnapshot {
+    /// The last complete datapoint
+    pub last:           Snapshot,
+    /// The unfinished datapoint
+    pub current:        Snapshot,
+    /// Including the unfinished datapoint
+    pub cumulative:     Snapshot,
+    /// Exact sum of the cumulative measurements
+    pub sum:            Duration,
+    /// Included in every measurement, e.g. the start/stop overhead measured by the producer
+    pub clock_overhead: Duration,
+    /// Datapoints completed since the previous snapshot, oldest first
+    pub new_datapoints: Vec<Datapoint>,
+    /// Datapoints completed so far
+    pub datapoints:     u64,
+    /// Messages since the previous snapshot
+    pub n_messages:     usize,
+    /// Calls per second since the previous snapshot, including those the producer didn't sample
+    pub throughput:     f64,
+    /// Fraction of the calls since the previous snapshot that were measured
+    pub sampled:        f64,
+    /// Messages missed because the producer sped past us
+    pub dropped:        usize,
+    pub bounds:         Bounds,
+    /// The upper bound in ticks, the lower of `bounds.max` and the auto bound
+    pub max_accepted:   Duration,
+    /// Measurements outside the bounds, see [`Bounds`]
+    pub rejected_below: usize,
+    pub rejected_above: usize,
+}
+
+impl TimingSnapshot {
+    /// The last complete datapoint, or the unfinished one until the first is complete
+    pub fn latest(&self) -> &Snapshot {
+        if self.datapoints == 0 {
+            &self.current
+        } else {
+            &self.last
+        }
+    }
+
+    /// Without the clock overhead, which leaves none to correct by
+    pub fn corrected(&self) -> Self {
+        let correct = |s: Snapshot| s.map(|t| t.saturating_sub(self.clock_overhead));
+        let overhead = self.clock_overhead.0.saturating_mul(self.cumulative.n as u64);
+        Self { last: correct(self.last),
+               current: correct(self.current),
+               cumulative: correct(self.cumulative),
+               sum: Duration(self.sum.0.saturating_sub(overhead)),
+               new_datapoints: self.new_datapoints
+                                   .iter()
+                                   .map(|d| Datapoint { stats: correct(d.stats), ..*d })
+                                   .collect(),
+               clock_overhead: Duration::ZERO,
+               ..self.clone() }
+    }
+}
+
+/// A datapoint of `samples_per_datapoint` measurements
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Datapoint {
+    /// Nanos since the unix epoch
+    pub completed: Nanos,
+    pub stats:     Snapshot,
+}
+
+/// Counts the messages a consumer missed from gaps in the producer's sequence numbers
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SeqTracker {
+    last: Option<u64>,
+}
+
+impl SeqTracker {
+    /// Number of messages the producer sent between the last one we saw and `seq`.
+    /// A sequence number that goes backwards means the producer restarted.
+    pub(crate) fn gap(&mut self, seq: u64) -> usize {
+        let gap = match self.last {
+            Some(last) if seq > last + 1 => (seq - last - 1) as usize,
+            _ => 0,
+        };
+        self.last = Some(seq);
+        gap
+    }
+}
+
+/// Consumes messages until `track` reports `n_samples` finished datapoints or the queue is empty
+pub(crate) fn drain<T: Copy + Default>(consumer: &mut Consumer<'static, T>, n_samples: usize, mut track: impl FnMut(&T) -> bool) {
+    let mut msg = Default::default();
+    let mut n = 0;
+    while n < n_samples {
+        match consumer.try_consume(&mut msg) {
+            Ok(()) => {
+                if track(&msg) {
+                    n += 1;
+                };
+            }
+            Err(ReadError::Empty) => break,
+            Err(ReadError::SpedPast) => consumer.recover_after_error_dumb(),
+        }
+    }
+}
+
+pub(crate) fn open_consumer<T: Copy>(path: String) -> Consumer<'static, T> {
+    let queue = ma_queues::Queue::shared(path, crate::QUEUE_SIZE, ma_queues::QueueType::SPMC).expect("couldn't open queue");
+    Consumer::from(queue)
+}
+
+/// Count, sum and maximum of the measurements in a [`PayloadGroup`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GroupStats {
+    pub n:     usize,
+    pub total: Duration,
+    pub max:   Duration,
+}
+
+impl GroupStats {
+    fn track(&mut self, el: Duration) {
+        self.n += 1;
+        self.total += el;
+        self.max = self.max.max(el);
+    }
+
+    pub fn avg(&self) -> Duration {
+        if self.n == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.n
+        }
+    }
+}
+
+/// Timings of the messages sharing one value of the grouped by payload field,
+/// see [`StatsEngine::set_group_by`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayloadGroup {
+    pub value:    payload::Value,
+    pub latency:  GroupStats,
+    pub business: GroupStats,
+}
+
+/// Filters and groups the messages of a timer by the fields of its [`Payload`](crate::Payload)
+#[derive(Debug, Default)]
+struct PayloadView {
+    layout:   payload::Layout,
+    // Only messages whose field equals the value are tracked
+    filters:  Vec<(payload::Field, String)>,
+    group_by: Option<usize>,
+    // Since the last snapshot
+    groups:   HashMap<u64, PayloadGroup>,
+}
+
+impl PayloadView {
+    /// Filters on fields that are not part of `layout` are ignored.
+    fn new(layout: payload::Layout, filters: &[(String, String)]) -> Self {
+        let filters = filters.iter()
+                             .filter_map(|(name, value)| layout.field(name).map(|f| (f.clone(), value.clone())))
+                             .collect();
+        Self { layout, filters, group_by: None, groups: HashMap::new() }
+    }
+
+    fn accepts(&self, msg: &TimingMessage) -> bool {
+        self.filters.iter().all(|(field, value)| self.layout.decode(field, msg).matches(value))
+    }
+
+    fn track(&mut self, msg: &TimingMessage, latency: bool) {
+        let Some(field) = self.group_by.map(|i| &self.layout.fields[i]) else {
+            return;
+        };
+        let value = self.layout.decode(field, msg);
+        let group = self.groups.entry(value.key()).or_insert(PayloadGroup { value,
+                                                                            latency: Default::default(),
+                                                                            business: Default::default() });
+        if latency {
+            group.latency.track(msg.elapsed());
+        } else {
+            group.business.track(msg.elapsed());
+        }
+    }
+
+    /// Groups by `field`, or stops grouping if the payload has no such field.
+    fn set_group_by(&mut self, field: Option<&str>) {
+        self.group_by = field.and_then(|name| self.layout.fields.iter().position(|f| f.name == name));
+        self.groups.clear();
+    }
+
+    fn group_by(&self) -> Option<String> {
+        self.group_by.map(|i| self.layout.fields[i].name.clone())
+    }
+}
+
+/// Sums and maxima of the perf counter deltas of the calls since the previous snapshot,
+/// see [`Timer::enable_perf_counters`](crate::Timer::enable_perf_counters)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PerfStats {
+    /// Calls with perf counters
+    pub n:        u64,
+    /// Whether instructions and cycles were counted
+    pub hardware: bool,
+    pub total:    PerfMessage,
+    pub max:      PerfMessage,
+}
+
+impl PerfStats {
+    fn track(&mut self, msg: &PerfMessage) {
+        self.n += 1;
+        self.hardware = msg.hardware;
+        self.total.context_switches += msg.context_switches;
+        self.total.page_faults += msg.page_faults;
+        self.total.instructions += msg.instructions;
+        self.total.cycles += msg.cycles;
+        self.max.context_switches = self.max.context_switches.max(msg.context_switches);
+        self.max.page_faults = self.max.page_faults.max(msg.page_faults);
+        self.max.instructions = self.max.instructions.max(msg.instructions);
+        self.max.cycles = self.max.cycles.max(msg.cycles);
+    }
+}
+
+/// An outlier together with the wall clock time its timer was stopped at
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Outlier {
+    /// Nanos since the unix epoch
+    pub stop: Nanos,
+    pub msg:  OutlierMessage,
+}
+
+impl Outlier {
+    /// Producers share our TSC, so how long ago it was stopped tells us when
+    fn new(msg: OutlierMessage, now: Instant, now_wall: Nanos) -> Self {
+        let stop = now_wall.saturating_sub(Nanos::from(Duration(now.0.saturating_sub(msg.msg.stop_t.0))));
+        Self { stop, msg }
+    }
+}
+
+/// Latency and business timings of one logical timer, merged over all producers
+/// that share its name, e.g. the per-thread producers of a [`SharedTimer`](crate::SharedTimer)
+struct TimerData {
+    name:               String,
+    latency_data:       TimingData,
+    business_data:      TimingData,
+    // Queue names of the merged producers, `name` or `name#pid.id`
+    producers:          Vec<String>,
+    latency_consumers:  Vec<(Consumer<'static, TimingMessage>, SeqTracker)>,
+    business_consumers: Vec<(Consumer<'static, TimingMessage>, SeqTracker)>,
+    payload:            PayloadView,
+    // Queue names of the producers that have an outlier queue
+    outlier_producers:  Vec<String>,
+    outlier_consumers:  Vec<Consumer<'static, OutlierMessage>>,
+    // Not taken by a snapshot yet, at most `MAX_OUTLIERS` of the latest
+    outliers:           Vec<Outlier>,
+    n_outliers:         usize,
+    // Queue names of the producers with perf counters enabled
+    perf_producers:     Vec<String>,
+    perf_consumers:     Vec<Consumer<'static, PerfMessage>>,
+    perf:               PerfStats,
+    // Header of the first producer
+    header:             Option<QueueHeader>,
+    // Indices of the groups the timer belongs to, see `GroupData`
+    group_ids:          Vec<usize>,
+}
+impl TimerData {
+    pub fn new(name: String,
+               layout: payload::Layout,
+               samples_per_datapoint: usize,
+               n_datapoints: usize,
+               significant_digits: u8,
+               filters: &[(String, String)],
+               group_ids: Vec<usize>)
+               -> Self {
+        let new_data = || TimingData::new(samples_per_datapoint, n_datapoints, Duration::ZERO, significant_digits);
+        Self { latency_data: new_data(),
+               business_data: new_data(),
+               producers: Vec::new(),
+               latency_consumers: Vec::new(),
+               business_consumers: Vec::new(),
+               payload: PayloadView::new(layout, filters),
+               outlier_producers: Vec::new(),
+               outlier_consumers: Vec::new(),
+               outliers: Vec::new(),
+               n_outliers: 0,
+               perf_producers: Vec::new(),
+               perf_consumers: Vec::new(),
+               perf: PerfStats::default(),
+               header: None,
+               group_ids,
+               name }
+    }
+
+    /// Business measurements are corrected by the start/stop overhead the first producer measured.
+    /// Latencies end with a single clock read and stay uncorrected.
+    pub fn add_producer(&mut self, queue_name: &str, header: QueueHeader) {
+        if self.header.is_none() {
+            self.business_data.set_clock_overhead(Duration(header.overhead.median));
+            self.header = Some(header);
+        }
+        self.latency_consumers
+            .push((open_consumer(format!("{}/latency-{queue_name}", crate::QUEUE_DIR)), SeqTracker::default()));
+        self.business_consumers
+            .push((open_consumer(format!("{}/timing-{queue_name}", crate::QUEUE_DIR)), SeqTracker::default()));
+        self.producers.push(queue_name.to_string());
+    }
+
+    pub fn add_perf_producer(&mut self, queue_name: &str) {
+        self.perf_consumers.push(open_consumer(format!("{}/perf-{queue_name}", crate::QUEUE_DIR)));
+        self.perf_producers.push(queue_name.to_string());
+    }
+
+    pub fn add_outlier_producer(&mut self, queue_name: &str) {
+        self.outlier_consumers.push(open_consumer(format!("{}/outliers-{queue_name}", crate::QUEUE_DIR)));
+        self.outlier_producers.push(queue_name.to_string());
+    }
+
+    pub fn set_bounds(&mut self, bounds: Bounds) {
+        self.latency_data.set_bounds(bounds);
+        self.business_data.set_bounds(bounds);
+    }
+
+    /// Stops consuming the queues of `queue_name`, keeping what was measured so far
+    pub fn remove_producer(&mut self, queue_name: &str) {
+        if let Some(i) = self.producers.iter().position(|p| p == queue_name) {
+            self.producers.remove(i);
+            self.latency_consumers.remove(i);
+            self.business_consumers.remove(i);
+        }
+        if let Some(i) = self.perf_producers.iter().position(|p| p == queue_name) {
+            self.perf_producers.remove(i);
+            self.perf_consumers.remove(i);
+        }
+        if let Some(i) = self.outlier_producers.iter().position(|p| p == queue_name) {
+            self.outlier_producers.remove(i);
+            self.outlier_consumers.remove(i);
+        }
+    }
+
+    /// Also tracks the messages in the timer's `groups`
+    pub fn consume(&mut self, n_samples: usize, groups: &mut [GroupData]) {
+        let Self { latency_data, business_data, latency_consumers, business_consumers, payload, group_ids, .. } = self;
+        // Gaps and calls count whether or not the message is within the bounds, which are checked once.
+        // Groups see what the timer accepts, within their own bounds.
+        for (consumer, seq) in latency_consumers {
+            drain(consumer, n_samples, |msg| {
+                let dropped = seq.gap(msg.seq);
+                latency_data.dropped += dropped;
+                group_ids.iter().for_each(|&g| groups[g].latency_data.dropped += dropped);
+                if !payload.accepts(msg) {
+                    return false;
+                }
+                let (el, weight) = (msg.elapsed(), msg.weight.max(1));
+                latency_data.n_calls += weight;
+                group_ids.iter().for_each(|&g| groups[g].latency_data.n_calls += weight);
+                if !latency_data.accept(el) {
+                    return false;
+                }
+                payload.track(msg, true);
+                for &g in group_ids.iter() {
+                    groups[g].latency_data.track_elapsed(el);
+                }
+                latency_data.track_accepted(el)
+            });
+        }
+        for (consumer, seq) in business_consumers {
+            drain(consumer, n_samples, |msg| {
+                let dropped = seq.gap(msg.seq);
+                business_data.dropped += dropped;
+                group_ids.iter().for_each(|&g| groups[g].business_data.dropped += dropped);
+                if !payload.accepts(msg) {
+                    return false;
+                }
+                let (el, weight) = (msg.elapsed(), msg.weight.max(1));
+                business_data.n_calls += weight;
+                group_ids.iter().for_each(|&g| groups[g].business_data.n_calls += weight);
+                if !business_data.accept(el) {
+                    return false;
+                }
+                payload.track(msg, false);
+                for &g in group_ids.iter() {
+                    groups[g].business_data.track_elapsed(el);
+                }
+                business_data.track_accepted(el)
+            });
+        }
+        for consumer in &mut self.perf_consumers {
+            let perf = &mut self.perf;
+            drain(consumer, n_samples, |msg| {
+                perf.track(msg);
+                false
+            });
+        }
+        if self.outlier_consumers.is_empty() {
+            return;
+        }
+        let (now, now_wall) = (Instant::now(), Nanos::now());
+        let Self { outliers, n_outliers, outlier_consumers, .. } = self;
+        for consumer in outlier_consumers {
+            drain(consumer, n_samples, |msg| {
+                if outliers.len() == MAX_OUTLIERS {
+                    outliers.remove(0);
+                }
+                outliers.push(Outlier::new(*msg, now, now_wall));
+                *n_outliers += 1;
+                true
+            });
+        }
+    }
+
+    pub fn snapshot(&mut self) -> TimerSnapshot {
+        TimerSnapshot { name:           self.name.clone(),
+                        kind:           SnapshotKind::Timer,
+                        n_sources:      self.producers.len(),
+                        latency:        self.latency_data.snapshot(),
+                        business:       self.business_data.snapshot(),
+                        header:         self.header,
+                        outliers:       std::mem::take(&mut self.outliers),
+                        n_outliers:     self.n_outliers,
+                        perf:           std::mem::take(&mut self.perf),
+                        layout:         self.payload.layout.clone(),
+                        group_by:       self.payload.group_by(),
+                        payload_groups: self.payload.groups.drain().map(|(_, g)| g).collect(),
+                        ..Default::default() }
+    }
+}
+
+/// Combined timings of all timers below `name` in the hierarchy of dot-separated names,
+/// e.g. `gateway.binance` for `gateway.binance.decode` and `gateway.binance.publish`.
+/// Business measurements are corrected like those of the first timer with a producer.
+struct GroupData {
+    name:          String,
+    latency_data:  TimingData,
+    business_data: TimingData,
+    n_timers:      usize,
+    // Name of the timer whose overhead corrects the business measurements
+    overhead_from: Option<String>,
+}
+
+impl GroupData {
+    pub fn new(name: String, samples_per_datapoint: usize, n_datapoints: usize, significant_digits: u8) -> Self {
+        let new_data = || TimingData::new(samples_per_datapoint, n_datapoints, Duration::ZERO, significant_digits);
+        Self { latency_data: new_data(),
+               business_data: new_data(),
+               n_timers: 0,
+               overhead_from: None,
+               name }
+    }
+
+    pub fn set_bounds(&mut self, bounds: Bounds) {
+        self.latency_data.set_bounds(bounds);
+        self.business_data.set_bounds(bounds);
+    }
+
+    /// Corrects the business measurements by `overhead` of the timer `name`, unless another timer's came first
+    fn take_overhead(&mut self, name: &str, overhead: Duration) {
+        if self.overhead_from.is_none() {
+            self.business_data.set_clock_overhead(overhead);
+            self.overhead_from = Some(name.to_string());
+        }
+    }
+
+    pub fn snapshot(&mut self) -> TimerSnapshot {
+        TimerSnapshot { name:      self.name.clone(),
+                        kind:      SnapshotKind::Group,
+                        n_sources: self.n_timers,
+                        latency:   self.latency_data.snapshot(),
+                        business:  self.business_data.snapshot(),
+                        ..Default::default() }
+    }
+}
+
+/// Per-stage and cumulative latencies of a [`PipelineTimer`](crate::PipelineTimer).
+/// They are differences between checkpoints, which the start/stop overhead in the header
+/// doesn't describe, so they stay uncorrected.
+struct PipelineData {
+    name:        String,
+    stage_names: Vec<String>,
+    stages:      Vec<TimingData>,
+    cumulative:  Vec<TimingData>,
+    total:       TimingData,
+    consumer:    Consumer<'static, PipelineMessage>,
+    seq:         SeqTracker,
+    header:      QueueHeader,
+}
+
+impl PipelineData {
+    pub fn new(name: String,
+               stage_names: Vec<String>,
+               samples_per_datapoint: usize,
+               n_datapoints: usize,
+               significant_digits: u8,
+               header: QueueHeader)
+               -> Self {
+        let new_data = || TimingData::new(samples_per_datapoint, n_datapoints, Duration::ZERO, significant_digits);
+        Self { stages: stage_names.iter().map(|_| new_data()).collect(),
+               cumulative: stage_names.iter().map(|_| new_data()).collect(),
+               total: new_data(),
+               consumer: open_consumer(format!("{}/pipeline-{name}", crate::QUEUE_DIR)),
+               seq: SeqTracker::default(),
+               header,
+               name,
+               stage_names }
+    }
+
+    /// The bounds apply to each stage and the total alike
+    pub fn set_bounds(&mut self, bounds: Bounds) {
+        for data in self.stages.iter_mut().chain(&mut self.cumulative) {
+            data.set_bounds(bounds);
+        }
+        self.total.set_bounds(bounds);
+    }
+
+    /// Switches to the queue of a producer that restarted under the same name
+    pub fn reopen(&mut self, header: QueueHeader) {
+        self.consumer = open_consumer(format!("{}/pipeline-{}", crate::QUEUE_DIR, self.name));
+        self.seq = SeqTracker::default();
+        self.header = header;
+    }
+
+    pub fn consume(&mut self, n_samples: usize) {
+        let Self { stages, cumulative, total, consumer, seq, .. } = self;
+        drain(consumer, n_samples, |msg| {
+            total.dropped += seq.gap(msg.seq);
+            for (i, (stage, cumulative)) in stages.iter_mut().zip(cumulative.iter_mut()).enumerate() {
+                if let Some(el) = msg.stage_elapsed(i) {
+                    stage.track_elapsed(el);
+                }
+                if let Some(el) = msg.cumulative_elapsed(i) {
+                    cumulative.track_elapsed(el);
+                }
+            }
+            total.track(&msg.total())
+        });
+    }
+
+    pub fn snapshot(&mut self) -> TimerSnapshot {
+        let by_stage = |data: &mut Vec<TimingData>| -> Vec<(String, TimingSnapshot)> {
+            self.stage_names.iter().cloned().zip(data.iter_mut().map(TimingData::snapshot)).collect()
+        };
+        TimerSnapshot { name:              self.name.clone(),
+                        kind:              SnapshotKind::Pipeline,
+                        n_sources:         1,
+                        latency:           self.total.snapshot(),
+                        stages:            by_stage(&mut self.stages),
+                        cumulative_stages: by_stage(&mut self.cumulative),
+                        header:            Some(self.header),
+                        ..Default::default() }
+    }
+}
+
+/// What a [`TimerSnapshot`] is of
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SnapshotKind {
+    #[default]
+    Timer,
+    /// All timers below a dot-separated name, e.g. `gateway` for `gateway.decode` and `gateway.publish`
+    Group,
+    Pipeline,
+}
+
+/// Statistics of a timer, group or pipeline at the time it was taken
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TimerSnapshot {
+    pub name:              String,
+    pub kind:              SnapshotKind,
+    /// Producers merged into a timer, timers below a group, or 1 for a pipeline
+    pub n_sources:         usize,
+    /// End-to-end for a pipeline
+    pub latency:           TimingSnapshot,
+    /// Empty for a pipeline
+    pub business:          TimingSnapshot,
+    /// Per-stage latencies of a pipeline, by stage name
+    pub stages:            Vec<(String, TimingSnapshot)>,
+    /// Latencies from the start of a pipeline to the end of each stage, by stage name
+    pub cumulative_stages: Vec<(String, TimingSnapshot)>,
+    /// Of the first producer of a timer, or of a pipeline. None for a group.
+    pub header:            Option<QueueHeader>,
+    /// Outliers of a timer since the previous snapshot, at most the latest 64, oldest first
+    pub outliers:          Vec<Outlier>,
+    /// Outliers of a timer so far, including those left out of `outliers`
+    pub n_outliers:        usize,
+    /// Perf counters of a timer since the previous snapshot
+    pub perf:              PerfStats,
+    /// Payload fields of a timer, see [`StatsEngine::set_group_by`]
+    pub layout:            payload::Layout,
+    pub group_by:          Option<String>,
+    /// Timings since the previous snapshot by value of the `group_by` field
+    pub payload_groups:    Vec<PayloadGroup>,
+}
+
+impl TimerSnapshot {
+    /// The latency and business statistics, or those of a whole pipeline and of its stages, by name
+    pub fn channels(&self) -> Vec<(&str, &TimingSnapshot)> {
+        match self.kind {
+            SnapshotKind::Pipeline => {
+                std::iter::once(("end-to-end", &self.latency)).chain(self.stages.iter().map(|(name, t)| (name.as_str(), t)))
+                                                              .collect()
+            }
+            _ => vec![("latency", &self.latency), ("business", &self.business)],
+        }
+    }
+
+    /// Without the clock overhead, see [`TimingSnapshot::corrected`]
+    pub fn corrected(&self) -> Self {
+        Self { latency: self.latency.corrected(), business: self.business.corrected(), ..self.clone() }
+    }
+}
+
+/// Consumes the queues of all timers and pipelines and keeps their statistics.
+///
+/// [`poll_producers`](StatsEngine::poll_producers) picks up new producers and drops the ones
+/// that are gone, [`consume`](StatsEngine::consume) reads their messages, and
+/// [`snapshots`](StatsEngine::snapshots) reports the statistics whenever needed.
+pub struct StatsEngine {
+    samples_per_datapoint: usize,
+    n_datapoints:          usize,
+    filters:               Vec<(String, String)>,
+    significant_digits:    u8,
+    default_bounds:        Bounds,
+    // By timer, group or pipeline name
+    bounds:                HashMap<String, Bounds>,
+    watcher:               TimerWatcher,
+    timers:                Vec<TimerData>,
+    // Never removed, so the indices in `TimerData` stay valid
+    groups:                Vec<GroupData>,
+    pipelines:             Vec<PipelineData>,
+    // Producers whose header doesn't agree with us, and why
+    invalid:               Vec<(String, String)>,
+    // Those and the producers whose header can't be read yet
+    rejected:              Vec<(String, String)>,
+}
+
+impl StatsEngine {
+    /// Each datapoint of a timer holds `samples_per_datapoint` measurements, and the averages of
+    /// the last `n_datapoints` of them are kept.
+    pub fn new(samples_per_datapoint: usize, n_datapoints: usize) -> std::io::Result<Self> {
+        Ok(Self { samples_per_datapoint,
+                  n_datapoints,
+                  filters: Vec::new(),
+                  significant_digits: 2,
+                  default_bounds: Bounds::default(),
+                  bounds: HashMap::new(),
+                  watcher: discovery::watch_timers()?,
+                  timers: Vec::new(),
+                  groups: Vec::new(),
+                  pipelines: Vec::new(),
+                  invalid: Vec::new(),
+                  rejected: Vec::new() })
+    }
+
+    /// Only track messages whose payload `field` equals `value`, for each `(field, value)`.
+    /// Timers without such a payload field are not filtered. Applies to the timers found from now on.
+    pub fn set_filters(&mut self, filters: Vec<(String, String)>) {
+        self.filters = filters;
+    }
+
+    /// Precision of the reported percentiles in decimal digits, 2 by default. Panics unless it's
+    /// in 1..=[`MAX_SIGNIFICANT_DIGITS`]. Applies to the timers found from now on. Each timer, group
+    /// and pipeline stage keeps four [`Histogram`]s, about 230 KB at 2 digits and 1.8 MB at 3,
+    /// and every datapoint clears two of them.
+    pub fn set_significant_digits(&mut self, significant_digits: u8) {
+        assert!((1..=MAX_SIGNIFICANT_DIGITS).contains(&significant_digits),
+                "significant digits have to be in 1..={MAX_SIGNIFICANT_DIGITS}, got {significant_digits}");
+        self.significant_digits = significant_digits;
+    }
+
+    /// Bounds of the timers, groups and pipelines without their own, see [`set_bounds`](StatsEngine::set_bounds).
+    pub fn set_default_bounds(&mut self, bounds: Bounds) {
+        self.default_bounds = bounds;
+        let Self { timers, groups, pipelines, bounds: own, .. } = self;
+        timers.iter_mut().filter(|d| !own.contains_key(&d.name)).for_each(|d| d.set_bounds(bounds));
+        groups.iter_mut().filter(|d| !own.contains_key(&d.name)).for_each(|d| d.set_bounds(bounds));
+        pipelines.iter_mut().filter(|d| !own.contains_key(&d.name)).for_each(|d| d.set_bounds(bounds));
+    }
+
+    /// Only track the measurements of the timer, group or pipeline `name` within `bounds`.
+    pub fn set_bounds(&mut self, name: String, bounds: Bounds) {
+        self.timers.iter_mut().filter(|d| d.name == name).for_each(|d| d.set_bounds(bounds));
+        self.groups.iter_mut().filter(|d| d.name == name).for_each(|d| d.set_bounds(bounds));
+        self.pipelines.iter_mut().filter(|d| d.name == name).for_each(|d| d.set_bounds(bounds));
+        self.bounds.insert(name, bounds);
+    }
+
+    /// Bounds of the timer, group or pipeline `name`, its own or the default ones
+    pub fn bounds_of(&self, name: &str) -> Bounds {
+        self.bounds.get(name).copied().unwrap_or(self.default_bounds)
+    }
+
+    /// Groups the messages of the timer `name` by the payload `field`, reported in
+    /// [`TimerSnapshot::payload_groups`] from the next snapshot on. Stops grouping if `field` is none
+    /// or not part of the payload.
+    pub fn set_group_by(&mut self, name: &str, field: Option<&str>) {
+        self.timers.iter_mut().filter(|d| d.name == name).for_each(|d| d.payload.set_group_by(field));
+    }
+
+    /// Producers that are ignored, and why
+    pub fn rejected(&self) -> &[(String, String)] {
+        &self.rejected
+    }
+
+    /// Starts consuming the producers that appeared since the last call, and stops consuming
+    /// the ones that are gone, keeping what was measured so far.
+    pub fn poll_producers(&mut self) -> std::io::Result<()> {
+        for event in self.watcher.poll()? {
+            let info = match event {
+                TimerEvent::Added(info) | TimerEvent::Updated(info) => info,
+                TimerEvent::Removed(queue_name) => {
+                    for d in &mut self.timers {
+                        d.remove_producer(&queue_name);
+                    }
+                    self.invalid.retain(|(n, _)| *n != queue_name);
+                    continue;
+                }
+            };
+            let Ok(header) = info.header else {
+                continue;
+            };
+            self.invalid.retain(|(n, _)| *n != info.queue_name);
+            if let Err(e) = header.validate(info.kind) {
+                self.invalid.push((info.queue_name.clone(), e.to_string()));
+                continue;
+            }
+            match info.kind {
+                HeaderKind::Timer => self.add_timer_producer(&info, header),
+                HeaderKind::Pipeline => self.add_pipeline(&info, header),
+            }
+        }
+        self.rejected.clear();
+        self.rejected.extend(self.invalid.iter().cloned());
+        self.rejected.extend(self.watcher.pending().iter().map(|(n, e)| (n.clone(), e.to_string())));
+        Ok(())
+    }
+
+    fn add_timer_producer(&mut self, info: &TimerInfo, header: QueueHeader) {
+        let queue_name = info.queue_name.as_str();
+        // Producers sharing a timer are called `name#pid.id`, merge them under `name`
+        let id = match self.timers.iter().position(|d| d.name == info.name()) {
+            Some(id) => id,
+            None => {
+                let name = info.name();
+                let group_ids: Vec<usize> = name.match_indices('.').map(|(i, _)| self.group_id(&name[..i])).collect();
+                // All producers of a timer send the same payload, its layout is in the header of the first one
+                let mut data = TimerData::new(name.to_string(),
+                                              header.payload_layout().unwrap_or_default(),
+                                              self.samples_per_datapoint,
+                                              self.n_datapoints,
+                                              self.significant_digits,
+                                              &self.filters,
+                                              group_ids);
+                data.set_bounds(self.bounds_of(name));
+                self.timers.push(data);
+                self.timers.len() - 1
+            }
+        };
+        let d = &mut self.timers[id];
+        if !d.producers.iter().any(|p| p == queue_name) {
+            d.add_producer(queue_name, header);
+        }
+        for &g in &d.group_ids {
+            self.groups[g].take_overhead(&d.name, d.business_data.clock_overhead);
+        }
+        // Outlier and perf queues are created once enabled, possibly after we found the timer
+        if info.queue("perf-").is_some() && !d.perf_producers.iter().any(|p| p == queue_name) {
+            d.add_perf_producer(queue_name);
+        }
+        if info.queue("outliers-").is_some() && !d.outlier_producers.iter().any(|p| p == queue_name) {
+            d.add_outlier_producer(queue_name);
+        }
+    }
+
+    /// Index of the group `name`, which gets a new timer
+    fn group_id(&mut self, name: &str) -> usize {
+        let id = match self.groups.iter().position(|g| g.name == name) {
+            Some(id) => id,
+            None => {
+                let mut data =
+                    GroupData::new(name.to_string(), self.samples_per_datapoint, self.n_datapoints, self.significant_digits);
+                data.set_bounds(self.bounds_of(name));
+                self.groups.push(data);
+                self.groups.len() - 1
+            }
+        };
+        self.groups[id].n_timers += 1;
+        id
+    }
+
+    fn add_pipeline(&mut self, info: &TimerInfo, header: QueueHeader) {
+        let name = info.queue_name.as_str();
+        if let Some(d) = self.pipelines.iter_mut().find(|d| d.name == name) {
+            if d.header.created != header.created {
+                d.reopen(header);
+            }
+            return;
+        }
+        let Some(stages) = info.queue("stages-").and_then(|p| std::fs::read_to_string(p).ok()) else {
+            return;
+        };
+        let mut data = PipelineData::new(name.to_string(),
+                                         stages.lines().map(String::from).collect(),
+                                         self.samples_per_datapoint,
+                                         self.n_datapoints,
+                                         self.significant_digits,
+                                         header);
+        data.set_bounds(self.bounds_of(name));
+        self.pipelines.push(data);
+    }
+
+    /// Consumes up to a datapoint's worth of messages from each queue
+    pub fn consume(&mut self) {
+        for d in &mut self.timers {
+            d.consume(self.samples_per_datapoint, &mut self.groups);
+        }
+        for d in &mut self.pipelines {
+            d.consume(self.samples_per_datapoint);
+        }
+    }
+
+    /// Statistics of all timers, then the groups and pipelines, see [`TimingData::snapshot`]
+    pub fn snapshots(&mut self) -> Vec<TimerSnapshot> {
+        self.timers
+            .iter_mut()
+            .map(TimerData::snapshot)
+            .chain(self.groups.iter_mut().map(GroupData::snapshot))
+            .chain(self.pipelines.iter_mut().map(PipelineData::snapshot))
+            .collect()
+    }
+}
+
+/// The cumulative statistics of `snapshots` as a plain text table, one row per channel with any
+/// measurements, and the throughput since the previous snapshots.
+pub fn format_table(snapshots: &[TimerSnapshot]) -> String {
+    let header = ["timer", "channel", "msg/s", "n", "avg", "p50", "p90", "p99", "p99.9", "max", "dropped", "rejected"];
+    let mut rows: Vec<[String; 12]> = vec![header.map(String::from)];
+    for s in snapshots {
+        for (channel, t) in s.channels().into_iter().filter(|(_, t)| t.cumulative.n != 0) {
+            let c = &t.cumulative;
+            rows.push([s.name.clone(),
+                       channel.to_string(),
+                       format!("{:.0}", t.throughput),
+                       c.n.to_string(),
+                       c.avg.to_string(),
+                       c.median.to_string(),
+                       c.p90.to_string(),
+                       c.p99.to_string(),
+                       c.p999.to_string(),
+                       c.max.to_string(),
+                       t.dropped.to_string(),
+                       (t.rejected_below + t.rejected_above).to_string()]);
+        }
+    }
+    let mut widths = [0; 12];
+    for row in &rows {
+        for (w, v) in widths.iter_mut().zip(row) {
+            *w = (*w).max(v.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in &rows {
+        let mut line = String::new();
+        for (i, (v, w)) in row.iter().zip(widths).enumerate() {
+            // Names to the left, numbers to the right
+            if i < 2 {
+                line += &format!("{v:<w$}  ");
+            } else {
+                line += &format!("{v:>w$}  ");
+            }
+        }
+        table += line.trim_end();
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_from_seq_gaps() {
+        let mut seq = SeqTracker::default();
+        let dropped: usize = [0, 1, 2, 5, 6, 10].into_iter().map(|s| seq.gap(s)).sum();
+        assert_eq!(dropped, 5);
+
+        // producer restarted, its sequence starts over
+        assert_eq!(seq.gap(0), 0);
+        assert_eq!(seq.gap(1), 0);
+    }
+
+    #[test]
+    fn bounds_reject_measurements() {
+        assert_eq!("..auto".parse(), Ok(Bounds { auto: Some(DEFAULT_AUTO_PERCENTILE), ..Default::default() }));
+        assert_eq!("5..auto:99".parse(), Ok(Bounds { min: Some(Nanos(5)), max: None, auto: Some(99.0) }));
+        assert!("5..autox".parse::<Bounds>().is_err() && "5".parse::<Bounds>().is_err());
+
+        let mut data = TimingData::new(100, 10, Duration::ZERO, 2);
+        data.set_bounds(Bounds { auto: Some(50.0), ..Default::default() });
+        for i in 0..100 {
+            data.track_elapsed(Duration(100 + i % 2));
+        }
+        // The auto bound applies from the first datapoint on
+        assert!(!data.track_elapsed(Duration(10_000)));
+        assert_eq!((data.rejected_below, data.rejected_above), (0, 1));
+        // Rejected calls still count towards the throughput
+        data.track(&TimingMessage { stop_t: Instant(10_000), weight: 4, ..Default::default() });
+        assert_eq!((data.rejected_above, data.n_calls, data.n_messages), (2, 4, 100));
+    }
+
+    #[test]
+    #[should_panic(expected = "significant digits have to be in 1..=3")]
+    fn significant_digits_are_capped() {
+        StatsEngine::new(100, 10).unwrap().set_significant_digits(MAX_SIGNIFICANT_DIGITS + 1);
+    }
+
+    #[test]
+    fn snapshot_since_previous() {
+        let mut data = TimingData::new(100, 10, Duration(10), 2);
+        for i in 0..150 {
+            data.track(&TimingMessage { stop_t: Instant(110 + i % 2), weight: 2, ..Default::default() });
+        }
+        data.dropped = 3;
+        let s = data.snapshot();
+        // Only the cumulative statistics include the unfinished datapoint
+        assert_eq!((s.last.n, s.current.n, s.cumulative.n, s.cumulative.median), (100, 50, 150, Duration(110)));
+        assert_eq!((s.new_datapoints.len(), s.new_datapoints[0].stats.n), (1, 100));
+        assert_eq!((s.n_messages, s.sampled, s.dropped), (150, 0.5, 3));
+        assert_eq!(s.sum, Duration(75 * 110 + 75 * 111));
+        assert!(s.throughput > 0.0);
+
+        // The clock overhead is subtracted from each measurement, but not from the dispersions
+        let c = s.corrected();
+        assert_eq!((c.cumulative.median, c.new_datapoints[0].stats.median, c.clock_overhead), (Duration(100), Duration(100), Duration::ZERO));
+        assert_eq!((c.sum, c.cumulative.stddev), (Duration(75 * 110 + 75 * 111 - 150 * 10), s.cumulative.stddev));
+        assert_eq!(c.corrected(), c);
+
+        let s = data.snapshot();
+        assert_eq!((s.n_messages, s.throughput), (0, 0.0));
+        assert!(s.new_datapoints.is_empty());
+    }
+
+    #[test]
+    fn table_of_snapshots() {
+        let mut data = TimingData::new(100, 10, Duration::ZERO, 2);
+        data.track_elapsed(Duration(1500));
+        let timer = TimerSnapshot { name:      "gateway.decode".into(),
+                                    n_sources: 1,
+                                    latency:   data.snapshot(),
+                                    ..Default::default() };
+        let table = format_table(&[timer]);
+        let lines: Vec<&str> = table.lines().collect();
+        // Only the channels with measurements get a row
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("gateway.decode  latency "), "{table}");
+        assert_eq!(lines[0].find("rejected").map(|i| i + "rejected".len()), Some(lines[1].len()));
+    }
+}
@@ -2,6 +2,10 @@ use std::ffi::{c_char, CStr};
 
 use crate::Timer;
 
+// Has to match `ma_timing::Timer` in ma_ffi/include/ma_timing.h, which C++ callers allocate
+#[cfg(not(feature = "disabled"))]
+const _: () = assert!(std::mem::size_of::<Timer>() == 480 && std::mem::align_of::<Timer>() == 8);
+
 #[no_mangle]
 pub extern "C" fn InitTimer(name: *const c_char, dst: *mut Timer) {
     let t = Timer::new(unsafe { CStr::from_ptr(name).to_str().expect("Can not read string argument.") });
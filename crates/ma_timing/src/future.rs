@@ -0,0 +1,69 @@
+//! Timing of futures across `.await` points.
+//!
+//! ```no_run
+//! use ma_timing::future::TimedExt;
+//!
+//! async fn handle(timer: &ma_timing::SharedTimer) {
+//!     async {
+//!         // request handling with .awaits
+//!     }.timed(timer)
+//!      .await
+//! }
+//! ```
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ma_time::{Duration, Instant};
+
+use crate::SharedTimer;
+
+/// A future that reports to a [`SharedTimer`] when it completes: the wall time since it
+/// was first polled as latency, and the time spent inside its polls as business time.
+/// The difference between the two is the time it spent waiting.
+///
+/// Futures that are dropped before completing are not reported.
+#[derive(Debug)]
+pub struct TimedFuture<F> {
+    inner:      F,
+    timer:      SharedTimer,
+    first_poll: Option<Instant>,
+    busy:       Duration,
+}
+
+impl<F: Future> TimedFuture<F> {
+    pub fn new(inner: F, timer: SharedTimer) -> Self {
+        Self { inner, timer, first_poll: None, busy: Duration::ZERO }
+    }
+}
+
+impl<F: Future> Future for TimedFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `inner` is never moved out of `self`, the other fields are not pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+
+        let start = Instant::now();
+        let first_poll = *this.first_poll.get_or_insert(start);
+        let poll = inner.poll(cx);
+        this.busy += Duration(Instant::now().0.saturating_sub(start.0));
+
+        if poll.is_ready() {
+            this.timer.record(first_poll, this.busy);
+        }
+        poll
+    }
+}
+
+pub trait TimedExt: Future + Sized {
+    /// Wraps this future in a [`TimedFuture`] reporting to `timer`.
+    fn timed(self, timer: &SharedTimer) -> TimedFuture<Self> {
+        TimedFuture::new(self, timer.clone())
+    }
+}
+
+impl<F: Future> TimedExt for F {}
@@ -0,0 +1,202 @@
+//! Describes the queues of a producer, so consumers can check they agree on their format
+//! before reading them.
+use std::fmt::Display;
+
+use ma_time::{Duration, Nanos};
+
+use crate::{
+    histogram::Histogram,
+    messages::{PipelineMessage, TimingMessage, MAX_STAGES, PAYLOAD_SIZE},
+    payload::Layout,
+    utils::{bytes_of, nul_padded, until_nul},
+    QUEUE_DIR, QUEUE_SIZE,
+};
+
+pub const MAGIC: u64 = u64::from_le_bytes(*b"MATIMING");
+/// Bumped whenever the header or any message changes
+pub const FORMAT_VERSION: u32 = 3;
+/// Number of empty `start`/`stop` pairs timed for [`Overhead`]
+const OVERHEAD_SAMPLES: usize = 10_000;
+/// Room for the serialized [`Layout`] of the payload
+const LAYOUT_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum HeaderKind {
+    Timer = 1,
+    Pipeline = 2,
+}
+
+/// What an empty [`Timer::start`](crate::Timer::start)/[`stop`](crate::Timer::stop) pair
+/// measures on the producer, in TSC ticks. Every business measurement includes it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Overhead {
+    pub min:    u64,
+    pub median: u64,
+    pub p99:    u64,
+    pub max:    u64,
+}
+
+impl Overhead {
+    /// Times `start_stop`, which has to take a measurement into the message the way
+    /// [`Timer::start`](crate::Timer::start) and [`stop`](crate::Timer::stop) do.
+    pub fn measure(mut start_stop: impl FnMut(&mut TimingMessage)) -> Self {
+        let mut msg = TimingMessage::default();
+        let mut histogram = Histogram::new(2);
+        for _ in 0..OVERHEAD_SAMPLES {
+            start_stop(&mut msg);
+            histogram.record(std::hint::black_box(&msg).elapsed());
+        }
+        let [median, p99] = histogram.percentiles([50.0, 99.0]);
+        Self { min: histogram.min().0, median: median.0, p99: p99.0, max: histogram.max().0 }
+    }
+}
+
+/// Written as raw bytes to `header-<name>` in [`QUEUE_DIR`] by each producer when it creates its queues.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct QueueHeader {
+    pub magic:                 u64,
+    pub version:               u32,
+    pub kind:                  u32,
+    pub queue_size:            u64,
+    pub message_size:          u32,
+    pub pipeline_message_size: u32,
+    pub payload_size:          u32,
+    pub max_stages:            u32,
+    pub pid:                   u32,
+    reserved:                  u32,
+    /// Nanos since the unix epoch
+    pub created:               u64,
+    /// TSC ticks per second, to convert the timestamps in the messages
+    pub tsc_frequency:         u64,
+    /// Name of the producer's executable, nul padded
+    pub exe:                   [u8; 64],
+    pub overhead:              Overhead,
+    /// [`Layout::serialize`] of the payload the producer sends, nul padded
+    pub payload_layout:        [u8; LAYOUT_SIZE],
+}
+
+/// TSC ticks per second
+pub(crate) fn tsc_frequency() -> u64 {
+    let nanos_per_billion_ticks = Nanos::from(Duration(1_000_000_000)).0.max(1);
+    (1e18 / nanos_per_billion_ticks as f64) as u64
+}
+
+#[derive(Debug)]
+pub enum HeaderError {
+    Io(std::io::Error),
+    Magic,
+    Version(u32),
+    Kind(u32),
+    Layout(&'static str),
+}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderError::Io(e) => write!(f, "couldn't read header: {e}"),
+            HeaderError::Magic => write!(f, "not a ma_timing header"),
+            HeaderError::Version(v) => write!(f, "format version {v}, expected {FORMAT_VERSION}"),
+            HeaderError::Kind(k) => write!(f, "unexpected kind of queue {k}"),
+            HeaderError::Layout(what) => write!(f, "{what} doesn't match"),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+impl QueueHeader {
+    pub fn new(kind: HeaderKind, overhead: Overhead, layout: &Layout) -> Self {
+        let exe = std::env::current_exe().ok()
+                                         .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                                         .unwrap_or_default();
+        let layout = layout.serialize();
+        assert!(layout.len() < LAYOUT_SIZE, "payload layout doesn't fit in the queue header");
+        Self { magic: MAGIC,
+               version: FORMAT_VERSION,
+               kind: kind as u32,
+               queue_size: QUEUE_SIZE as u64,
+               message_size: std::mem::size_of::<TimingMessage>() as u32,
+               pipeline_message_size: std::mem::size_of::<PipelineMessage>() as u32,
+               payload_size: PAYLOAD_SIZE as u32,
+               max_stages: MAX_STAGES as u32,
+               pid: std::process::id(),
+               reserved: 0,
+               created: Nanos::now().0,
+               tsc_frequency: tsc_frequency(),
+               exe: nul_padded(&exe),
+               overhead,
+               payload_layout: nul_padded(&layout) }
+    }
+
+    pub fn exe(&self) -> &str {
+        until_nul(&self.exe)
+    }
+
+    pub fn payload_layout(&self) -> Result<Layout, String> {
+        Layout::deserialize(until_nul(&self.payload_layout))
+    }
+
+    /// Reads the header of the producer `name`, checking only that it is one of ours
+    /// with the same format version, see [`validate`](QueueHeader::validate).
+    pub fn read(name: &str) -> Result<Self, HeaderError> {
+        let bytes = std::fs::read(format!("{QUEUE_DIR}/header-{name}")).map_err(HeaderError::Io)?;
+        if bytes.len() < 12 || bytes[..8] != MAGIC.to_ne_bytes() {
+            return Err(HeaderError::Magic);
+        }
+        let version = u32::from_ne_bytes(bytes[8..12].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(HeaderError::Version(version));
+        }
+        if bytes.len() != std::mem::size_of::<Self>() {
+            return Err(HeaderError::Layout("header size"));
+        }
+        Ok(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Self) })
+    }
+
+    /// Checks that the producer's queues can be read as `kind` by this build.
+    pub fn validate(&self, kind: HeaderKind) -> Result<(), HeaderError> {
+        if self.kind != kind as u32 {
+            return Err(HeaderError::Kind(self.kind));
+        }
+        let checks = [(self.queue_size == QUEUE_SIZE as u64, "queue size"),
+                      (self.message_size == std::mem::size_of::<TimingMessage>() as u32, "timing message size"),
+                      (self.pipeline_message_size == std::mem::size_of::<PipelineMessage>() as u32,
+                       "pipeline message size"),
+                      (self.payload_size == PAYLOAD_SIZE as u32, "payload size"),
+                      (self.max_stages == MAX_STAGES as u32, "maximum number of stages"),
+                      (self.payload_layout().is_ok(), "payload layout")];
+        match checks.into_iter().find(|(ok, _)| !ok) {
+            Some((_, what)) => Err(HeaderError::Layout(what)),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg_attr(feature = "disabled", allow(dead_code))]
+    pub(crate) fn write(name: &str, kind: HeaderKind, overhead: Overhead, layout: &Layout) {
+        std::fs::write(format!("{QUEUE_DIR}/header-{name}"), bytes_of(&Self::new(kind, overhead, layout)))
+            .expect("couldn't write queue header");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_layout() {
+        let overhead = Overhead::measure(|msg| {
+            msg.start_now();
+            msg.stop_now();
+        });
+        let header = QueueHeader::new(HeaderKind::Timer, overhead, &Layout::of::<u32>());
+        assert!(header.validate(HeaderKind::Timer).is_ok());
+        assert!(matches!(header.validate(HeaderKind::Pipeline), Err(HeaderError::Kind(1))));
+        let mismatched = QueueHeader { message_size: 32, ..header };
+        assert!(matches!(mismatched.validate(HeaderKind::Timer), Err(HeaderError::Layout("timing message size"))));
+        assert!(overhead.min <= overhead.median && overhead.median <= overhead.p99 && overhead.p99 <= overhead.max);
+        assert_eq!(header.payload_layout().unwrap(), Layout::of::<u32>());
+    }
+}
@@ -1,16 +1,56 @@
-use std::fmt::Display;
-
 pub mod messages;
+pub mod payload;
+#[cfg(not(feature = "disabled"))]
+mod perf;
+#[cfg(not(feature = "disabled"))]
+pub mod pipeline;
+pub mod prometheus;
+pub mod recorder;
+#[cfg(not(feature = "disabled"))]
+pub mod registry;
+pub mod sampling;
+pub mod sink;
+#[cfg(not(feature = "disabled"))]
+pub mod shared;
+pub mod stats;
 pub mod throughput;
 #[cfg(feature = "timekeeper")]
 pub mod timekeeper;
+#[cfg(all(feature = "tracing", not(feature = "disabled")))]
+pub mod tracing;
 #[cfg(feature = "timekeeper")]
 pub use timekeeper::TimeKeeper;
+pub mod discovery;
+pub mod engine;
 pub mod ffi;
+pub mod header;
+pub mod histogram;
+#[cfg(not(feature = "disabled"))]
+pub mod future;
+pub mod lifecycle;
+mod local;
+mod macros;
+#[cfg(not(feature = "disabled"))]
+mod timer;
 pub mod utils;
 
-use ma_time::Instant;
+#[cfg(feature = "disabled")]
+mod disabled;
+#[cfg(feature = "disabled")]
+pub use disabled::{future, pipeline, registry, shared, Timer};
+#[cfg(all(feature = "tracing", feature = "disabled"))]
+pub use disabled::tracing;
+pub use engine::{StatsEngine, TimerSnapshot};
+pub use histogram::Histogram;
+pub use lifecycle::gc;
+pub use local::{LocalSnapshot, LocalTimer};
+pub use payload::Payload;
+pub use pipeline::PipelineTimer;
+pub use sampling::Sampling;
+pub use shared::SharedTimer;
 pub use throughput::ThroughputSampler;
+#[cfg(not(feature = "disabled"))]
+pub use timer::Timer;
 /// Where are the latency ma_queues stored
 #[cfg(target_os = "windows")]
 const QUEUE_DIR: &str = "Global";
@@ -19,84 +59,6 @@ const QUEUE_DIR: &str = "/dev/shm";
 /// The size of the latency ma_queues
 const QUEUE_SIZE: usize = 2usize.pow(17);
 
-#[repr(C)]
-pub struct Timer {
-    pub curmsg: messages::TimingMessage,
-    timing_producer: ma_queues::Producer<'static, messages::TimingMessage>,
-    latency_producer: ma_queues::Producer<'static, messages::TimingMessage>,
-}
-
-impl Timer {
-    pub fn new<S: Display>(name: S) -> Self {
-        let _ = std::fs::create_dir(QUEUE_DIR);
-        let timing_queue = ma_queues::Queue::shared(
-            format!("{QUEUE_DIR}/timing-{name}"),
-            QUEUE_SIZE,
-            ma_queues::QueueType::SPMC,
-        )
-        .expect("couldn't open timing queue");
-        let latency_queue = ma_queues::Queue::shared(
-            format!("{QUEUE_DIR}/latency-{name}"),
-            QUEUE_SIZE,
-            ma_queues::QueueType::SPMC,
-        )
-        .expect("couldn't open latency queue");
-
-        Timer {
-            curmsg: Default::default(),
-            timing_producer: ma_queues::Producer::from(timing_queue),
-            latency_producer: ma_queues::Producer::from(latency_queue),
-        }
-    }
-}
-
-unsafe impl Send for Timer {}
-unsafe impl Sync for Timer {}
-
-impl Timer {
-    pub fn start(&mut self) {
-        self.set_start(Instant::now());
-        #[cfg(target_arch = "x86_64")]
-        unsafe{ std::arch::x86_64::_mm_lfence() };
-    }
-    pub fn start_t(&self) -> &Instant {
-        &self.curmsg.start_t
-    }
-    pub fn stop_t(&self) -> &Instant {
-        &self.curmsg.stop_t
-    }
-    pub fn stop(&mut self) {
-        self.set_stop(Instant::now());
-        self.send_business();
-    }
-    pub fn stop_and_latency(&mut self, ingestion_t: Instant) {
-        self.stop();
-        self.set_start(ingestion_t);
-        self.send_latency();
-    }
-    pub fn set_stop(&mut self, stop: Instant) {
-        self.curmsg.stop_t = stop;
-    }
-    pub fn set_start(&mut self, start: Instant) {
-        self.curmsg.start_t = start;
-    }
-
-    pub fn latency(&mut self, ingestion_t: Instant) {
-        self.set_stop(Instant::now());
-        self.set_start(ingestion_t);
-        self.send_latency();
-    }
-    pub fn send_latency(&mut self) {
-        self.latency_producer
-            .produce(&self.curmsg);
-    }
-
-    pub fn send_business(&mut self) {
-        self.timing_producer
-            .produce(&self.curmsg);
-    }
-}
-
 pub fn init_logger() {
     fern::Dispatch::new()
         .format(|out, message, _| out.finish(format_args!("{}", message)))
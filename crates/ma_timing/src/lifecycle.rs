@@ -0,0 +1,123 @@
+//! Bookkeeping of the files producers leave in [`QUEUE_DIR`](crate::QUEUE_DIR).
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+use crate::QUEUE_DIR;
+
+/// Pid files of this process' producers by name, locked until it exits, see [`gc`]
+#[cfg(target_os = "linux")]
+static PID_LOCKS: once_cell::sync::Lazy<std::sync::Mutex<std::collections::HashMap<String, File>>> =
+    once_cell::sync::Lazy::new(Default::default);
+
+/// Directory the producers create their queues and metadata in
+pub fn queue_dir() -> &'static Path {
+    Path::new(QUEUE_DIR)
+}
+
+/// Prefixes of all the files a producer called `name` can create
+pub(crate) const PREFIXES: &[&str] = &["timing-", "latency-", "pipeline-", "stages-", "pid-", "outliers-", "perf-", "header-"];
+
+/// Panics if `name` can't be used for a timer. `#` separates the name from the index of the
+/// per-thread producers of a [`SharedTimer`](crate::SharedTimer), which would merge them with other timers.
+#[cfg_attr(feature = "disabled", allow(dead_code))]
+pub(crate) fn check_name(name: &str) {
+    assert!(!name.contains('#'), "timer names can't contain '#': {name}");
+}
+
+/// Records the current process as the producer of `name`, used by [`gc`].
+/// The pid file is written and locked under a temporary name, then renamed into place,
+/// so [`gc`] never sees it half written or unlocked.
+#[cfg_attr(feature = "disabled", allow(dead_code))]
+pub(crate) fn write_pid(name: &str) {
+    let pid = std::process::id();
+    let tmp = format!("{QUEUE_DIR}/.pid-{name}.{pid}");
+    let mut file = File::create(&tmp).expect("couldn't write producer pid");
+    file.write_all(pid.to_string().as_bytes()).expect("couldn't write producer pid");
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            panic!("couldn't lock producer pid: {}", std::io::Error::last_os_error());
+        }
+    }
+    std::fs::rename(&tmp, format!("{QUEUE_DIR}/pid-{name}")).expect("couldn't write producer pid");
+    #[cfg(target_os = "linux")]
+    PID_LOCKS.lock().unwrap().insert(name.to_string(), file);
+}
+
+/// Removes all queues and metadata belonging to the producer `name`.
+/// Consumers that already mapped the queues keep working until they drop them.
+pub fn unlink(name: &str) {
+    for prefix in PREFIXES {
+        let _ = std::fs::remove_file(format!("{QUEUE_DIR}/{prefix}{name}"));
+    }
+}
+
+/// Whether the producer that wrote `pid_file` still holds its lock, i.e. hasn't exited.
+/// Unlike looking up the pid this also works after the pid was reused, and for producers
+/// in other pid namespaces sharing the queue directory.
+#[cfg(target_os = "linux")]
+fn producer_alive(pid_file: &File) -> bool {
+    use std::os::fd::AsRawFd;
+    // Our lock, if we got it, is released when `pid_file` is closed
+    unsafe { libc::flock(pid_file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) != 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn producer_alive(_pid_file: &File) -> bool {
+    true
+}
+
+/// Unlinks the queues of all producers whose process no longer exists,
+/// returning their names. Queues without a readable pid are left alone.
+pub fn gc() -> std::io::Result<Vec<String>> {
+    let mut removed = Vec::new();
+    for entry in std::fs::read_dir(QUEUE_DIR)?.filter_map(|e| e.ok()) {
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str().and_then(|n| n.strip_prefix("pid-")) else {
+            continue;
+        };
+        let Ok(mut pid_file) = File::open(entry.path()) else {
+            continue;
+        };
+        let mut pid = String::new();
+        if pid_file.read_to_string(&mut pid).is_err() || pid.trim().parse::<u32>().is_err() {
+            continue;
+        }
+        if producer_alive(&pid_file) {
+            continue;
+        }
+        unlink(name);
+        removed.push(name.to_string());
+    }
+    Ok(removed)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gc_removes_unlocked_producers() {
+        let name = format!("lifecycle-test-{}", std::process::id());
+        let pid_file = format!("{QUEUE_DIR}/pid-{name}");
+        let queue = format!("{QUEUE_DIR}/timing-{name}");
+        std::fs::write(&queue, "").unwrap();
+
+        std::fs::write(&pid_file, "").unwrap();
+        assert!(!gc().unwrap().contains(&name));
+        assert!(Path::new(&queue).exists());
+
+        write_pid(&name);
+        assert!(!gc().unwrap().contains(&name));
+        assert!(Path::new(&queue).exists());
+
+        // As if the producer exited, even though its pid is still alive
+        PID_LOCKS.lock().unwrap().remove(&name);
+        assert!(gc().unwrap().contains(&name));
+        assert!(!Path::new(&queue).exists() && !Path::new(&pid_file).exists());
+    }
+}
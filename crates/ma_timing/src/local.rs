@@ -0,0 +1,146 @@
+use ma_time::{Duration, Instant};
+
+use crate::{
+    histogram::Histogram,
+    stats::{Dispersion, Snapshot},
+};
+
+/// Statistics of the measurements a [`LocalTimer`] collected so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LocalSnapshot {
+    pub latency:  Snapshot,
+    pub business: Snapshot,
+}
+
+/// A timer that aggregates in-process instead of sending to the timekeeper, e.g. to assert on
+/// latency budgets in tests.
+///
+/// Measurements go into a [`Histogram`] per channel like in the timekeeper, so memory stays
+/// constant until [`reset`](LocalTimer::reset). It keeps measuring with the `disabled` feature
+/// since it never touches shared memory.
+///
+/// ```
+/// let mut timer = ma_timing::LocalTimer::new();
+/// for _ in 0..100 {
+///     timer.start();
+///     std::hint::black_box((0..100).sum::<u64>());
+///     timer.stop();
+/// }
+/// let snapshot = timer.snapshot();
+/// assert_eq!(snapshot.business.n, 100);
+/// assert!(snapshot.business.p99 <= snapshot.business.max);
+/// ```
+#[derive(Clone, Debug)]
+pub struct LocalTimer {
+    // Taken by the `stop` that ends the call
+    start_t:   Option<Instant>,
+    business:  Channel,
+    latencies: Channel,
+}
+
+impl Default for LocalTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalTimer {
+    /// Keeps 2 significant digits, like the timekeeper by default.
+    pub fn new() -> Self {
+        Self::with_significant_digits(2)
+    }
+
+    /// See [`Histogram::new`] for the precision and memory.
+    pub fn with_significant_digits(significant_digits: u8) -> Self {
+        Self { start_t:   None,
+               business:  Channel::new(significant_digits),
+               latencies: Channel::new(significant_digits), }
+    }
+
+    #[inline(always)]
+    pub fn start(&mut self) {
+        self.start_t = Some(Instant::now());
+        #[cfg(target_arch = "x86_64")]
+        unsafe{ std::arch::x86_64::_mm_lfence() };
+    }
+
+    /// Ignored without a `start` since the previous `stop`.
+    #[inline(always)]
+    pub fn stop(&mut self) {
+        if let Some(start_t) = self.start_t.take() {
+            self.business.track(Duration::elapsed(start_t));
+        }
+    }
+
+    pub fn stop_and_latency(&mut self, ingestion_t: Instant) {
+        self.stop();
+        self.latency(ingestion_t);
+    }
+
+    pub fn latency(&mut self, ingestion_t: Instant) {
+        self.latencies.track(Duration::elapsed(ingestion_t));
+    }
+
+    /// Records a call that was measured elsewhere, see [`Timer::record`](crate::Timer::record).
+    pub fn record(&mut self, ingestion_t: Instant, busy: Duration) {
+        self.business.track(busy);
+        self.latency(ingestion_t);
+    }
+
+    /// The percentiles are within the precision of the histograms.
+    pub fn snapshot(&self) -> LocalSnapshot {
+        LocalSnapshot { latency:  self.latencies.snapshot(),
+                        business: self.business.snapshot(), }
+    }
+
+    pub fn reset(&mut self) {
+        self.business.clear();
+        self.latencies.clear();
+    }
+}
+
+/// Measurements of one channel, kept the way the timekeeper keeps them
+#[derive(Clone, Debug)]
+struct Channel {
+    histogram:  Histogram,
+    dispersion: Dispersion,
+}
+
+impl Channel {
+    fn new(significant_digits: u8) -> Self {
+        Self { histogram:  Histogram::new(significant_digits),
+               dispersion: Dispersion::default(), }
+    }
+
+    #[inline]
+    fn track(&mut self, t: Duration) {
+        self.histogram.record(t);
+        self.dispersion.track(t);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot::from_histogram(&self.histogram, &self.dispersion)
+    }
+
+    fn clear(&mut self) {
+        self.histogram.clear();
+        self.dispersion = Dispersion::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_without_start_is_ignored() {
+        let mut timer = LocalTimer::new();
+        timer.stop();
+        timer.start();
+        timer.stop();
+        timer.stop();
+        let business = timer.snapshot().business;
+        assert_eq!(business.n, 1);
+        assert!(business.max < Duration(Instant::now().0 / 2));
+    }
+}
@@ -0,0 +1,42 @@
+/// Returns the calling thread's [`TimerHandle`] for `name`, lazily creating the timer on first use.
+/// The handle is cached per call site so `name` should be the same every time it is evaluated.
+///
+/// ```no_run
+/// ma_timing::timer!("decode").start();
+/// // decode
+/// ma_timing::timer!("decode").stop();
+/// ```
+#[macro_export]
+macro_rules! timer {
+    ($name:expr) => {{
+        ::std::thread_local! {
+            static HANDLE: ::std::cell::Cell<::std::option::Option<$crate::registry::TimerHandle>> =
+                const { ::std::cell::Cell::new(::std::option::Option::None) };
+        }
+        HANDLE.with(|h| match h.get() {
+            ::std::option::Option::Some(t) => t,
+            ::std::option::Option::None => {
+                let t = $crate::registry::local($name);
+                h.set(::std::option::Option::Some(t));
+                t
+            }
+        })
+    }};
+}
+
+/// Times the evaluation of `$body` with the calling thread's timer for `$name`,
+/// returning the value of `$body`. Early returns from `$body` skip the measurement.
+///
+/// ```no_run
+/// let decoded = ma_timing::timed!("decode", { 21 * 2 });
+/// ```
+#[macro_export]
+macro_rules! timed {
+    ($name:expr, $body:expr) => {{
+        let timer = $crate::timer!($name);
+        timer.start();
+        let out = $body;
+        timer.stop();
+        out
+    }};
+}
@@ -1,10 +1,22 @@
 use ma_time::{Duration, Instant};
 
-#[derive(Clone, Copy, Default, Debug)]
+/// Number of bytes available for a [`Payload`](crate::payload::Payload), chosen so a
+/// [`TimingMessage`] fills exactly one cache line.
+pub const PAYLOAD_SIZE: usize = 32;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
 #[repr(C)]
 pub struct TimingMessage {
     pub start_t: Instant,
     pub stop_t: Instant,
+    /// Per-queue sequence number set by the producer right before sending,
+    /// used by consumers to count messages they missed.
+    pub seq: u64,
+    /// Number of calls this message stands for when the producer samples,
+    /// see [`Sampling`](crate::Sampling).
+    pub weight: u64,
+    /// Raw bytes of the producer's [`Payload`](crate::payload::Payload), zeroed if it has none.
+    pub payload: [u8; PAYLOAD_SIZE],
 }
 
 impl TimingMessage {
@@ -13,10 +25,122 @@ impl TimingMessage {
         Self {
             start_t: Instant::now(),
             stop_t: Default::default(),
+            seq: 0,
+            weight: 1,
+            payload: [0; PAYLOAD_SIZE],
         }
     }
 
     pub fn elapsed(&self) -> Duration {
         Duration(self.stop_t.0 - self.start_t.0)
     }
+
+    /// Takes the start time the way [`Timer::start`](crate::Timer::start) does, fencing so the
+    /// timed code can't start before the clock is read.
+    #[inline(always)]
+    pub fn start_now(&mut self) {
+        self.start_at(Instant::now());
+    }
+
+    /// Like [`start_now`](Self::start_now), with a clock reading that was just taken.
+    #[inline(always)]
+    pub fn start_at(&mut self, start_t: Instant) {
+        self.start_t = start_t;
+        #[cfg(target_arch = "x86_64")]
+        unsafe{ std::arch::x86_64::_mm_lfence() };
+    }
+
+    #[inline(always)]
+    pub fn stop_now(&mut self) {
+        self.stop_t = Instant::now();
+    }
+}
+
+/// A [`TimingMessage`] that took longer than the outlier threshold of its timer,
+/// see [`Timer::set_outlier_threshold`](crate::Timer::set_outlier_threshold).
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+#[repr(C)]
+pub struct OutlierMessage {
+    pub msg: TimingMessage,
+    /// Set by the producer with [`Timer::set_tag`](crate::Timer::set_tag)
+    pub tag: u64,
+    /// The cpu the producer ran on when it sent `msg`
+    pub cpu: u32,
+    /// Whether `msg` went to the latency or the business queue
+    pub latency: bool,
+}
+
+/// Counter deltas between `start` and `stop` of a [`Timer`](crate::Timer) with perf counters enabled
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+#[repr(C)]
+pub struct PerfMessage {
+    /// `seq` of the business [`TimingMessage`] these were measured for
+    pub seq: u64,
+    pub context_switches: u64,
+    pub page_faults: u64,
+    /// Only counted if `hardware` is set
+    pub instructions: u64,
+    pub cycles: u64,
+    /// Whether hardware counters were available
+    pub hardware: bool,
+}
+
+/// Maximum number of stages a [`PipelineTimer`](crate::PipelineTimer) can track.
+pub const MAX_STAGES: usize = 8;
+
+/// One message moving through a pipeline.
+/// `checkpoints[0]` is when it entered the first stage, `checkpoints[i + 1]`
+/// when it finished stage `i`. Stages that were not reached are left at zero.
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct PipelineMessage {
+    pub checkpoints: [Instant; MAX_STAGES + 1],
+    pub seq: u64,
+}
+
+impl PipelineMessage {
+    /// Time spent in stage `stage`, measured from the last checkpoint before it.
+    pub fn stage_elapsed(&self, stage: usize) -> Option<Duration> {
+        let stop = self.checkpoints[stage + 1];
+        if stop == Instant::ZERO {
+            return None;
+        }
+        let start = self.checkpoints[..=stage].iter().rev().find(|t| **t != Instant::ZERO)?;
+        Some(Duration(stop.0 - start.0))
+    }
+
+    /// Time from entering the pipeline up to the end of stage `stage`.
+    pub fn cumulative_elapsed(&self, stage: usize) -> Option<Duration> {
+        let stop = self.checkpoints[stage + 1];
+        if stop == Instant::ZERO || self.checkpoints[0] == Instant::ZERO {
+            return None;
+        }
+        Some(Duration(stop.0 - self.checkpoints[0].0))
+    }
+
+    /// Start to last reached checkpoint as a plain [`TimingMessage`].
+    pub fn total(&self) -> TimingMessage {
+        let stop_t = *self.checkpoints[1..].iter().rev().find(|t| **t != Instant::ZERO).unwrap_or(&self.checkpoints[0]);
+        TimingMessage { start_t: self.checkpoints[0], stop_t, seq: self.seq, weight: 1, payload: [0; PAYLOAD_SIZE] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipeline_skipped_stage() {
+        let mut msg = PipelineMessage::default();
+        msg.checkpoints[0] = Instant(100);
+        msg.checkpoints[1] = Instant(110);
+        // stage 1 skipped
+        msg.checkpoints[3] = Instant(150);
+
+        assert_eq!(msg.stage_elapsed(0), Some(Duration(10)));
+        assert_eq!(msg.stage_elapsed(1), None);
+        assert_eq!(msg.stage_elapsed(2), Some(Duration(40)));
+        assert_eq!(msg.cumulative_elapsed(2), Some(Duration(50)));
+        assert_eq!(msg.total().elapsed(), Duration(50));
+    }
 }
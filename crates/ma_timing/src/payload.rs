@@ -0,0 +1,285 @@
+//! User defined payloads sent along with the timestamps of a [`Timer`](crate::Timer).
+//!
+//! Payloads are copied into the fixed size [`TimingMessage::payload`] area so consumers
+//! don't need to know their type. A [`Layout`] describing their numeric fields is written
+//! into the [`QueueHeader`](crate::header::QueueHeader), which lets the timekeeper decode and group by them.
+//!
+//! ```
+//! #[derive(Clone, Copy)]
+//! #[repr(C)]
+//! struct Order {
+//!     id:       u64,
+//!     quantity: f64,
+//! }
+//! ma_timing::impl_payload!(Order { id: u64, quantity: f64 });
+//! ```
+use std::{fmt::Display, str::FromStr};
+
+use crate::messages::{TimingMessage, PAYLOAD_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl FieldKind {
+    pub fn size(&self) -> usize {
+        match self {
+            FieldKind::U8 | FieldKind::I8 => 1,
+            FieldKind::U16 | FieldKind::I16 => 2,
+            FieldKind::U32 | FieldKind::I32 | FieldKind::F32 => 4,
+            FieldKind::U64 | FieldKind::I64 | FieldKind::F64 => 8,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldKind::U8 => "u8",
+            FieldKind::U16 => "u16",
+            FieldKind::U32 => "u32",
+            FieldKind::U64 => "u64",
+            FieldKind::I8 => "i8",
+            FieldKind::I16 => "i16",
+            FieldKind::I32 => "i32",
+            FieldKind::I64 => "i64",
+            FieldKind::F32 => "f32",
+            FieldKind::F64 => "f64",
+        }
+    }
+}
+
+impl FromStr for FieldKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "u8" => FieldKind::U8,
+            "u16" => FieldKind::U16,
+            "u32" => FieldKind::U32,
+            "u64" => FieldKind::U64,
+            "i8" => FieldKind::I8,
+            "i16" => FieldKind::I16,
+            "i32" => FieldKind::I32,
+            "i64" => FieldKind::I64,
+            "f32" => FieldKind::F32,
+            "f64" => FieldKind::F64,
+            _ => return Err(format!("unknown field kind {s}")),
+        })
+    }
+}
+
+/// Numeric types that can be a field of a [`Payload`]
+pub trait Numeric: Copy {
+    const KIND: FieldKind;
+}
+
+macro_rules! numeric {
+    ($($t:ty => $kind:ident),*) => {
+        $(impl Numeric for $t {
+            const KIND: FieldKind = FieldKind::$kind;
+        })*
+    };
+}
+numeric!(u8 => U8, u16 => U16, u32 => U32, u64 => U64, i8 => I8, i16 => I16, i32 => I32, i64 => I64, f32 => F32, f64 => F64);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name:   String,
+    pub offset: usize,
+    pub kind:   FieldKind,
+}
+
+/// A small `Copy` value that is sent with each [`TimingMessage`].
+///
+/// # Safety
+/// The type has to fit in [`PAYLOAD_SIZE`] bytes and `fields` has to describe where
+/// its numeric fields actually are. Use [`impl_payload!`](crate::impl_payload) rather
+/// than implementing this by hand.
+pub unsafe trait Payload: Copy + 'static {
+    fn fields() -> Vec<Field>;
+}
+
+unsafe impl Payload for () {
+    fn fields() -> Vec<Field> {
+        Vec::new()
+    }
+}
+
+macro_rules! numeric_payload {
+    ($($t:ty),*) => {
+        $(unsafe impl Payload for $t {
+            fn fields() -> Vec<Field> {
+                vec![Field { name: "value".into(), offset: 0, kind: <$t as Numeric>::KIND }]
+            }
+        })*
+    };
+}
+numeric_payload!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Implements [`Payload`] for a `#[repr(C)]` struct, listing the numeric fields
+/// that should be visible to the timekeeper. The listed kinds are checked against the fields:
+///
+/// ```compile_fail
+/// #[derive(Clone, Copy)]
+/// #[repr(C)]
+/// struct Order { id: u32 }
+/// ma_timing::impl_payload!(Order { id: u64 });
+/// ```
+#[macro_export]
+macro_rules! impl_payload {
+    ($t:ty { $($field:ident: $kind:ty),* $(,)? }) => {
+        const _: () = assert!(::std::mem::size_of::<$t>() <= $crate::messages::PAYLOAD_SIZE,
+                              "payload doesn't fit in a TimingMessage");
+        unsafe impl $crate::payload::Payload for $t {
+            fn fields() -> ::std::vec::Vec<$crate::payload::Field> {
+                // The listed kind has to be the field's real type, otherwise it gets decoded as garbage
+                $(let _: fn(&$t) -> $kind = |p| p.$field;)*
+                ::std::vec![$($crate::payload::Field {
+                    name: ::std::string::String::from(::std::stringify!($field)),
+                    offset: ::std::mem::offset_of!($t, $field),
+                    kind: <$kind as $crate::payload::Numeric>::KIND,
+                }),*]
+            }
+        }
+    };
+}
+
+/// Writes `payload` into the payload area of `msg`.
+#[cfg_attr(feature = "disabled", allow(dead_code))]
+#[inline(always)]
+pub(crate) fn write<P: Payload>(msg: &mut TimingMessage, payload: P) {
+    debug_assert!(std::mem::size_of::<P>() <= PAYLOAD_SIZE);
+    unsafe { std::ptr::write_unaligned(msg.payload.as_mut_ptr() as *mut P, payload) }
+}
+
+/// A decoded payload field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+}
+
+impl Value {
+    /// Bit pattern that can be used to group by this value
+    pub fn key(&self) -> u64 {
+        match self {
+            Value::Unsigned(v) => *v,
+            Value::Signed(v) => *v as u64,
+            Value::Float(v) => v.to_bits(),
+        }
+    }
+
+    /// Whether this equals `s` parsed as the same kind of number
+    pub fn matches(&self, s: &str) -> bool {
+        match self {
+            Value::Unsigned(v) => s.parse::<u64>().is_ok_and(|s| s == *v),
+            Value::Signed(v) => s.parse::<i64>().is_ok_and(|s| s == *v),
+            Value::Float(v) => s.parse::<f64>().is_ok_and(|s| s == *v),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Unsigned(v) => v.fmt(f),
+            Value::Signed(v) => v.fmt(f),
+            Value::Float(v) => v.fmt(f),
+        }
+    }
+}
+
+/// The fields of the payload a producer sends.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Layout {
+    pub fields: Vec<Field>,
+}
+
+impl Layout {
+    pub fn of<P: Payload>() -> Self {
+        Self { fields: P::fields() }
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub fn decode(&self, field: &Field, msg: &TimingMessage) -> Value {
+        let bytes = &msg.payload[field.offset..field.offset + field.kind.size()];
+        macro_rules! read {
+            ($t:ty) => {
+                <$t>::from_ne_bytes(bytes.try_into().unwrap())
+            };
+        }
+        match field.kind {
+            FieldKind::U8 => Value::Unsigned(read!(u8) as u64),
+            FieldKind::U16 => Value::Unsigned(read!(u16) as u64),
+            FieldKind::U32 => Value::Unsigned(read!(u32) as u64),
+            FieldKind::U64 => Value::Unsigned(read!(u64)),
+            FieldKind::I8 => Value::Signed(read!(i8) as i64),
+            FieldKind::I16 => Value::Signed(read!(i16) as i64),
+            FieldKind::I32 => Value::Signed(read!(i32) as i64),
+            FieldKind::I64 => Value::Signed(read!(i64)),
+            FieldKind::F32 => Value::Float(read!(f32) as f64),
+            FieldKind::F64 => Value::Float(read!(f64)),
+        }
+    }
+
+    /// One `name offset kind` line per field
+    pub fn serialize(&self) -> String {
+        self.fields.iter().map(|f| format!("{} {} {}\n", f.name, f.offset, f.kind.as_str())).collect()
+    }
+
+    pub fn deserialize(s: &str) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        for line in s.lines().filter(|l| !l.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some(offset), Some(kind)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(format!("malformed payload field {line}"));
+            };
+            let offset = offset.parse::<usize>().map_err(|e| e.to_string())?;
+            let kind = kind.parse::<FieldKind>()?;
+            if offset + kind.size() > PAYLOAD_SIZE {
+                return Err(format!("payload field {name} out of bounds"));
+            }
+            fields.push(Field { name: name.to_string(), offset, kind });
+        }
+        Ok(Self { fields })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    struct Order {
+        id:       u64,
+        side:     i8,
+        quantity: f64,
+    }
+    crate::impl_payload!(Order { id: u64, side: i8, quantity: f64 });
+
+    #[test]
+    fn roundtrip() {
+        let layout = Layout::of::<Order>();
+        assert_eq!(Layout::deserialize(&layout.serialize()).unwrap(), layout);
+
+        let mut msg = TimingMessage::default();
+        write(&mut msg, Order { id: 42, side: -1, quantity: 0.5 });
+        let decoded: Vec<_> = layout.fields.iter().map(|f| layout.decode(f, &msg)).collect();
+        assert_eq!(decoded, vec![Value::Unsigned(42), Value::Signed(-1), Value::Float(0.5)]);
+        assert!(decoded[0].matches("42"));
+    }
+}
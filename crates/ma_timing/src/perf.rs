@@ -0,0 +1,190 @@
+//! Counters from Linux `perf_event_open` read around the timed code of a [`Timer`](crate::Timer),
+//! enabled with [`Timer::enable_perf_counters`](crate::Timer::enable_perf_counters).
+//!
+//! Counters are opened as one group for the calling thread: cycles and instructions when the
+//! hardware and `perf_event_paranoid` allow it, always context switches and page faults.
+use crate::messages::PerfMessage;
+
+/// Counter deltas of the timed code, sent along with each business message
+#[cfg_attr(not(all(feature = "perf", target_os = "linux")), allow(dead_code))]
+pub(crate) struct Perf {
+    #[cfg(all(feature = "perf", target_os = "linux"))]
+    counters: imp::Counters,
+    start:    PerfMessage,
+    producer: ma_queues::Producer<'static, PerfMessage>,
+}
+
+impl Perf {
+    #[cfg(feature = "perf")]
+    pub(crate) fn new(name: &str) -> std::io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        {
+            let counters = imp::Counters::open()?;
+            let queue = ma_queues::Queue::shared(format!("{}/perf-{name}", crate::QUEUE_DIR),
+                                                 crate::QUEUE_SIZE,
+                                                 ma_queues::QueueType::SPMC).expect("couldn't open perf queue");
+            Ok(Self { counters, start: Default::default(), producer: ma_queues::Producer::from(queue) })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn start(&mut self) {
+        #[cfg(all(feature = "perf", target_os = "linux"))]
+        self.counters.read(&mut self.start);
+    }
+
+    /// Sends the deltas since `start` for the business message with sequence number `seq`
+    #[inline(always)]
+    pub(crate) fn stop(&mut self, seq: u64) {
+        #[cfg(all(feature = "perf", target_os = "linux"))]
+        {
+            let mut msg = PerfMessage::default();
+            self.counters.read(&mut msg);
+            msg.seq = seq;
+            msg.context_switches = msg.context_switches.saturating_sub(self.start.context_switches);
+            msg.page_faults = msg.page_faults.saturating_sub(self.start.page_faults);
+            msg.instructions = msg.instructions.saturating_sub(self.start.instructions);
+            msg.cycles = msg.cycles.saturating_sub(self.start.cycles);
+            self.producer.produce(&msg);
+        }
+        #[cfg(not(all(feature = "perf", target_os = "linux")))]
+        let _ = seq;
+    }
+}
+
+#[cfg(all(feature = "perf", target_os = "linux"))]
+mod imp {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use crate::messages::PerfMessage;
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_TYPE_SOFTWARE: u32 = 1;
+    const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+    const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+    const PERF_COUNT_SW_PAGE_FAULTS: u64 = 2;
+    const PERF_COUNT_SW_CONTEXT_SWITCHES: u64 = 3;
+    const PERF_FORMAT_GROUP: u64 = 1 << 3;
+    const EXCLUDE_KERNEL: u64 = 1 << 5;
+    const EXCLUDE_HV: u64 = 1 << 6;
+
+    /// `struct perf_event_attr` up to `PERF_ATTR_SIZE_VER5`
+    #[repr(C)]
+    #[derive(Default)]
+    struct PerfEventAttr {
+        kind:               u32,
+        size:               u32,
+        config:             u64,
+        sample_period:      u64,
+        sample_type:        u64,
+        read_format:        u64,
+        flags:              u64,
+        wakeup_events:      u32,
+        bp_type:            u32,
+        config1:            u64,
+        config2:            u64,
+        branch_sample_type: u64,
+        sample_regs_user:   u64,
+        sample_stack_user:  u32,
+        clockid:            i32,
+        sample_regs_intr:   u64,
+        aux_watermark:      u32,
+        sample_max_stack:   u16,
+        reserved:           u16,
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum Counter {
+        Cycles,
+        Instructions,
+        ContextSwitches,
+        PageFaults,
+    }
+
+    impl Counter {
+        fn attr(&self) -> (u32, u64) {
+            match self {
+                Counter::Cycles => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_CPU_CYCLES),
+                Counter::Instructions => (PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS),
+                Counter::ContextSwitches => (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_CONTEXT_SWITCHES),
+                Counter::PageFaults => (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS),
+            }
+        }
+
+        fn set(&self, msg: &mut PerfMessage, v: u64) {
+            match self {
+                Counter::Cycles => msg.cycles = v,
+                Counter::Instructions => msg.instructions = v,
+                Counter::ContextSwitches => msg.context_switches = v,
+                Counter::PageFaults => msg.page_faults = v,
+            }
+        }
+    }
+
+    fn open(counter: Counter, group: Option<&OwnedFd>) -> std::io::Result<OwnedFd> {
+        let (kind, config) = counter.attr();
+        let group_fd = group.map_or(-1, |g| g.as_raw_fd());
+        // Without privileges only user space may be counted, try that when counting everything is refused
+        for flags in [EXCLUDE_HV, EXCLUDE_HV | EXCLUDE_KERNEL] {
+            let attr = PerfEventAttr { kind,
+                                       size: std::mem::size_of::<PerfEventAttr>() as u32,
+                                       config,
+                                       read_format: PERF_FORMAT_GROUP,
+                                       flags,
+                                       ..Default::default() };
+            let fd = unsafe {
+                libc::syscall(libc::SYS_perf_event_open, &attr as *const PerfEventAttr, 0, -1, group_fd, 0)
+            };
+            if fd >= 0 {
+                return Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) });
+            }
+        }
+        Err(std::io::Error::last_os_error())
+    }
+
+    /// A group of counters of the calling thread
+    pub(super) struct Counters {
+        // The first is the group leader, reading it reads all of them
+        fds:      Vec<OwnedFd>,
+        counters: Vec<Counter>,
+    }
+
+    impl Counters {
+        fn open_group(counters: &[Counter]) -> std::io::Result<Self> {
+            let mut fds: Vec<OwnedFd> = Vec::with_capacity(counters.len());
+            for counter in counters {
+                fds.push(open(*counter, fds.first())?);
+            }
+            Ok(Self { fds, counters: counters.to_vec() })
+        }
+
+        /// Falls back to only the software counters if the hardware ones can't be opened
+        pub(super) fn open() -> std::io::Result<Self> {
+            use Counter::*;
+            Self::open_group(&[Cycles, Instructions, ContextSwitches, PageFaults])
+                .or_else(|_| Self::open_group(&[ContextSwitches, PageFaults]))
+        }
+
+        /// Sets the current counter values in `msg`
+        #[inline(always)]
+        pub(super) fn read(&self, msg: &mut PerfMessage) {
+            // nr followed by one value per counter
+            let mut buf = [0u64; 5];
+            let n = unsafe {
+                libc::read(self.fds[0].as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, std::mem::size_of_val(&buf))
+            };
+            if n <= 0 {
+                return;
+            }
+            for (counter, v) in self.counters.iter().zip(&buf[1..=buf[0].min(4) as usize]) {
+                counter.set(msg, *v);
+            }
+            msg.hardware = self.counters.len() == 4;
+        }
+    }
+}
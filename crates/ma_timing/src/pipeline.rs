@@ -0,0 +1,97 @@
+use std::fmt::Display;
+
+use ma_time::Instant;
+
+use crate::{
+    header::{HeaderKind, QueueHeader},
+    messages::{PipelineMessage, MAX_STAGES},
+    payload::Layout,
+    timer,
+};
+
+/// Records one checkpoint per stage as a message moves through a pipeline,
+/// e.g. ingest -> decode -> decide -> send, and sends them all as a single message.
+///
+/// ```no_run
+/// let mut timer = ma_timing::PipelineTimer::new("gateway", &["ingest", "decode", "decide", "send"]);
+/// timer.start();
+/// // ingest
+/// timer.checkpoint(0);
+/// // decode
+/// timer.checkpoint(1);
+/// // decide
+/// timer.checkpoint(2);
+/// // send
+/// timer.checkpoint(3);
+/// timer.send();
+/// ```
+#[repr(C)]
+pub struct PipelineTimer {
+    pub curmsg: PipelineMessage,
+    producer:   ma_queues::Producer<'static, PipelineMessage>,
+    n_stages:   usize,
+    seq:        u64,
+}
+
+unsafe impl Send for PipelineTimer {}
+unsafe impl Sync for PipelineTimer {}
+
+impl PipelineTimer {
+    pub fn new<S: Display>(name: S, stages: &[&str]) -> Self {
+        assert!(!stages.is_empty() && stages.len() <= MAX_STAGES,
+                "a pipeline needs between 1 and {MAX_STAGES} stages");
+        crate::lifecycle::check_name(&name.to_string());
+        let _ = std::fs::create_dir(crate::QUEUE_DIR);
+        std::fs::write(format!("{}/stages-{name}", crate::QUEUE_DIR), stages.join("\n"))
+            .expect("couldn't write pipeline stages");
+        let queue = ma_queues::Queue::shared(
+            format!("{}/pipeline-{name}", crate::QUEUE_DIR),
+            crate::QUEUE_SIZE,
+            ma_queues::QueueType::SPMC,
+        )
+        .expect("couldn't open pipeline queue");
+        crate::lifecycle::write_pid(&name.to_string());
+        QueueHeader::write(&name.to_string(), HeaderKind::Pipeline, timer::overhead(), &Layout::default());
+
+        Self {
+            curmsg: Default::default(),
+            producer: ma_queues::Producer::from(queue),
+            n_stages: stages.len(),
+            seq: 0,
+        }
+    }
+
+    pub fn n_stages(&self) -> usize {
+        self.n_stages
+    }
+
+    /// Clears all checkpoints and marks the message as entering the pipeline now.
+    pub fn start(&mut self) {
+        self.start_at(Instant::now());
+        #[cfg(target_arch = "x86_64")]
+        unsafe{ std::arch::x86_64::_mm_lfence() };
+    }
+
+    /// Like [`start`](Self::start) but with an externally recorded ingestion time.
+    pub fn start_at(&mut self, ingestion_t: Instant) {
+        self.curmsg.checkpoints = Default::default();
+        self.curmsg.checkpoints[0] = ingestion_t;
+    }
+
+    /// Marks the end of `stage`.
+    pub fn checkpoint(&mut self, stage: usize) {
+        self.set_checkpoint(stage, Instant::now());
+    }
+
+    pub fn set_checkpoint(&mut self, stage: usize, t: Instant) {
+        debug_assert!(stage < self.n_stages, "stage {stage} out of bounds");
+        self.curmsg.checkpoints[stage + 1] = t;
+    }
+
+    /// Sends the recorded checkpoints.
+    pub fn send(&mut self) {
+        self.curmsg.seq = self.seq;
+        self.seq += 1;
+        self.producer.produce(&self.curmsg);
+    }
+}
@@ -0,0 +1,223 @@
+//! Serves [`TimerSnapshot`]s at `/metrics` in the Prometheus text exposition format.
+//!
+//! Each channel of a timer, e.g. its latency, is a summary of all its measurements so far
+//! labeled by `timer`, `channel` and `kind`, next to gauges of its dispersion and counters of the
+//! dropped and rejected messages.
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use ma_time::{Duration, Nanos};
+
+use crate::engine::{SnapshotKind, TimerSnapshot};
+
+const QUANTILES: [&str; 5] = ["0.5", "0.9", "0.99", "0.999", "0.9999"];
+/// How long a scrape may take in total, from reading the request to writing the last byte
+const SCRAPE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// All channels with measurements or dropped messages in the text exposition format
+pub fn format_metrics(snapshots: &[TimerSnapshot]) -> String {
+    let mut durations = family("ma_timing_duration_seconds",
+                               "summary",
+                               "Measured durations since the timekeeper started, the count is the number of messages");
+    let dispersion_help = [("stddev", "Standard deviation"),
+                           ("mad", "Median absolute deviation from the median"),
+                           ("jitter", "Mean absolute difference between consecutive measurements")];
+    let mut dispersions = dispersion_help.map(|(name, help)| {
+                                             let name = format!("ma_timing_duration_{name}_seconds");
+                                             let family = family(&name, "gauge", &format!("{help} of the measured durations"));
+                                             (name, family)
+                                         });
+    let mut dropped = family("ma_timing_dropped_total",
+                             "counter",
+                             "Messages missed because the producer sped past the timekeeper");
+    let mut rejected = family("ma_timing_rejected_total", "counter", "Measurements outside the bounds of the timer");
+    let mut throughput = family("ma_timing_throughput", "gauge", "Calls per second since the previous snapshot");
+    let mut sources = family("ma_timing_sources", "gauge", "Producers merged into a timer, or timers below a group");
+    let seconds = |d: Duration| Nanos::from(d).0 as f64 / 1e9;
+    for s in snapshots {
+        let kind = match s.kind {
+            SnapshotKind::Timer => "timer",
+            SnapshotKind::Group => "group",
+            SnapshotKind::Pipeline => "pipeline",
+        };
+        sources += &format!("ma_timing_sources{{timer=\"{}\",kind=\"{kind}\"}} {}\n", escape(&s.name), s.n_sources);
+        for (channel, t) in s.channels() {
+            let rejected_total = t.rejected_below + t.rejected_above;
+            if t.cumulative.n == 0 && t.dropped == 0 && rejected_total == 0 {
+                continue;
+            }
+            let labels = format!("timer=\"{}\",channel=\"{}\",kind=\"{kind}\"", escape(&s.name), escape(channel));
+            let c = &t.cumulative;
+            for (q, v) in QUANTILES.iter().zip([c.median, c.p90, c.p99, c.p999, c.p9999]) {
+                durations += &format!("ma_timing_duration_seconds{{{labels},quantile=\"{q}\"}} {}\n", seconds(v));
+            }
+            durations += &format!("ma_timing_duration_seconds_sum{{{labels}}} {}\n", seconds(t.sum));
+            durations += &format!("ma_timing_duration_seconds_count{{{labels}}} {}\n", c.n);
+            for ((name, family), v) in dispersions.iter_mut().zip([c.stddev, c.mad, c.jitter]) {
+                *family += &format!("{name}{{{labels}}} {}\n", seconds(v));
+            }
+            dropped += &format!("ma_timing_dropped_total{{{labels}}} {}\n", t.dropped);
+            rejected += &format!("ma_timing_rejected_total{{{labels}}} {rejected_total}\n");
+            throughput += &format!("ma_timing_throughput{{{labels}}} {}\n", t.throughput);
+        }
+    }
+    let dispersions: String = dispersions.into_iter().map(|(_, family)| family).collect();
+    durations + &dispersions + &dropped + &rejected + &throughput + &sources
+}
+
+fn family(name: &str, kind: &str, help: &str) -> String {
+    format!("# HELP {name} {help}\n# TYPE {name} {kind}\n")
+}
+
+/// Label values escape backslashes, quotes and newlines
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// A minimal HTTP listener for Prometheus to scrape. Scrapes are answered one at a time on a thread of its own,
+/// so slow clients hold up neither the caller nor, beyond [`SCRAPE_TIMEOUT`], each other.
+pub struct MetricsServer {
+    addr:     SocketAddr,
+    metrics:  Arc<Mutex<String>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl MetricsServer {
+    /// E.g. `127.0.0.1:9464`, port 0 picks a free one, see [`local_addr`](MetricsServer::local_addr).
+    pub fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let metrics = Arc::new(Mutex::new(String::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (served, stop) = (metrics.clone(), shutdown.clone());
+        std::thread::Builder::new().name("metrics".into()).spawn(move || serve(listener, &served, &stop))?;
+        Ok(Self { addr, metrics, shutdown })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Serves `snapshots` from now on
+    pub fn update(&self, snapshots: &[TimerSnapshot]) {
+        let metrics = format_metrics(snapshots);
+        *self.metrics.lock().unwrap() = metrics;
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        // Wakes the listener up to see it's shut down
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(listener: TcpListener, metrics: &Mutex<String>, shutdown: &AtomicBool) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::Relaxed) {
+            return;
+        }
+        match stream {
+            Ok(stream) => {
+                let peer = stream.peer_addr();
+                if let Err(e) = respond(stream, metrics) {
+                    log::debug!("couldn't answer scrape from {peer:?}: {e}");
+                }
+            }
+            Err(e) => log::debug!("couldn't accept scrape: {e}"),
+        }
+    }
+}
+
+/// Time left until `deadline`, an error once it passed
+fn remaining(deadline: Instant) -> std::io::Result<std::time::Duration> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        Err(std::io::Error::new(ErrorKind::TimedOut, "scrape took too long"))
+    } else {
+        Ok(left)
+    }
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<String>) -> std::io::Result<()> {
+    let deadline = Instant::now() + SCRAPE_TIMEOUT;
+    // Only the request line matters, the rest of the head is read so the client sees a clean close
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        stream.set_read_timeout(Some(remaining(deadline)?))?;
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.lock().unwrap().clone()),
+        _ => ("404 Not Found", "not found, try /metrics\n".to_string()),
+    };
+    let response = format!("HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                           body.len());
+    let mut response = response.as_bytes();
+    while !response.is_empty() {
+        stream.set_write_timeout(Some(remaining(deadline)?))?;
+        match stream.write(response) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => response = &response[n..],
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::TimingSnapshot;
+
+    fn get(server: &MetricsServer, path: &str) -> String {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn scrape_metrics() {
+        let mut latency = TimingSnapshot { dropped: 3, sum: Duration(1_000_000_000), ..Default::default() };
+        latency.cumulative.n = 10;
+        let timer = TimerSnapshot { name: "gateway.\"decode\"".into(), n_sources: 2, latency, ..Default::default() };
+        let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        server.update(&[timer]);
+        // A client that never finishes its request only holds up the others until it times out
+        let mut stalled = TcpStream::connect(server.local_addr()).unwrap();
+        write!(stalled, "GET /metr").unwrap();
+
+        let response = get(&server, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        let labels = r#"timer="gateway.\"decode\"",channel="latency",kind="timer""#;
+        assert!(response.contains(&format!("ma_timing_duration_seconds_count{{{labels}}} 10\n")), "{response}");
+        assert!(response.contains(&format!("ma_timing_dropped_total{{{labels}}} 3\n")), "{response}");
+        let sum = Nanos::from(Duration(1_000_000_000)).0 as f64 / 1e9;
+        assert!(response.contains(&format!("ma_timing_duration_seconds_sum{{{labels}}} {sum}\n")), "{response}");
+        for name in ["stddev", "mad", "jitter"] {
+            assert!(response.contains(&format!("ma_timing_duration_{name}_seconds{{{labels}}} 0\n")), "{response}");
+        }
+        // Business has no measurements
+        assert!(!response.contains("channel=\"business\""), "{response}");
+
+        assert!(get(&server, "/").starts_with("HTTP/1.1 404"));
+    }
+}
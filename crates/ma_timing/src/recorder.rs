@@ -0,0 +1,399 @@
+//! Captures every [`TimingMessage`] of chosen timers to a binary file for offline analysis.
+//!
+//! A capture starts with a [`CaptureHeader`] describing the recording host, followed by records
+//! each starting with a tag byte:
+//! - a [`Record::Stream`] when a producer's queue is found, with the producer's [`QueueHeader`],
+//! - a [`Record::Message`] per consumed message, with the raw bytes of the message,
+//! - a [`Record::Dropped`] when the producer sped past the recorder.
+//!
+//! Everything is in the native byte order of the recording host.
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use ma_queues::Consumer;
+use ma_time::{Instant, Nanos};
+
+use crate::{
+    discovery::{watch_timers, TimerEvent, TimerWatcher},
+    engine::{drain, open_consumer, SeqTracker},
+    header::{tsc_frequency, HeaderKind, QueueHeader},
+    messages::TimingMessage,
+    utils::{bytes_of, nul_padded, until_nul},
+};
+
+pub const CAPTURE_MAGIC: u64 = u64::from_le_bytes(*b"MACAPTUR");
+/// Bumped whenever the capture header or the records change
+pub const CAPTURE_VERSION: u32 = 1;
+/// Messages consumed from one queue before moving on to the next
+const BATCH: usize = 4096;
+const BUFFER_SIZE: usize = 1 << 20;
+
+const STREAM: u8 = 1;
+const MESSAGE: u8 = 2;
+const DROPPED: u8 = 3;
+
+/// Starts a capture, written as raw bytes.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct CaptureHeader {
+    pub magic:             u64,
+    pub version:           u32,
+    /// Size of the [`TimingMessage`] of each [`Record::Message`]
+    pub message_size:      u32,
+    /// Size of the [`QueueHeader`] of each [`Record::Stream`]
+    pub queue_header_size: u32,
+    /// Of the recorder
+    pub pid:               u32,
+    /// Nanos since the unix epoch when recording started
+    pub started:           u64,
+    /// TSC ticks at `started`, to convert the timestamps in the messages to wall time
+    pub started_ticks:     u64,
+    /// TSC ticks per second of the recording host
+    pub tsc_frequency:     u64,
+    /// Name of the recording host, nul padded
+    pub host:              [u8; 64],
+}
+
+impl CaptureHeader {
+    fn new() -> Self {
+        let host = std::fs::read_to_string("/proc/sys/kernel/hostname").or_else(|_| std::env::var("HOSTNAME"))
+                                                                         .unwrap_or_default();
+        Self { magic: CAPTURE_MAGIC,
+               version: CAPTURE_VERSION,
+               message_size: std::mem::size_of::<TimingMessage>() as u32,
+               queue_header_size: std::mem::size_of::<QueueHeader>() as u32,
+               pid: std::process::id(),
+               started: Nanos::now().0,
+               started_ticks: Instant::now().0,
+               tsc_frequency: tsc_frequency(),
+               host: nul_padded(host.trim()) }
+    }
+
+    pub fn host(&self) -> &str {
+        until_nul(&self.host)
+    }
+}
+
+/// Which queue of a producer a stream records
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Channel {
+    Latency = 0,
+    Business = 1,
+}
+
+impl Channel {
+    fn prefix(self) -> &'static str {
+        match self {
+            Channel::Latency => "latency-",
+            Channel::Business => "timing-",
+        }
+    }
+}
+
+/// A queue of a producer, identified in the messages by `id`
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    pub id:         u32,
+    /// Name the producer was created with, `name#pid.id` for the producers of a shared timer
+    pub queue_name: String,
+    pub channel:    Channel,
+    /// Calibration of the producer, e.g. its start/stop overhead
+    pub header:     QueueHeader,
+}
+
+#[derive(Clone, Debug)]
+pub enum Record {
+    Stream(Box<StreamInfo>),
+    Message { stream: u32, msg: TimingMessage },
+    /// Number of messages of the stream missed since the previous record of it
+    Dropped { stream: u32, count: u64 },
+}
+
+/// What was recorded from a queue so far
+#[derive(Clone, Debug)]
+pub struct StreamStats {
+    pub queue_name: String,
+    pub channel:    Channel,
+    pub n_messages: u64,
+    pub dropped:    u64,
+}
+
+struct Stream {
+    stats:    StreamStats,
+    // None once the producer is gone
+    consumer: Option<Consumer<'static, TimingMessage>>,
+    seq:      SeqTracker,
+}
+
+fn write_stream(w: &mut impl Write, stream: &StreamInfo) -> std::io::Result<()> {
+    w.write_all(&[STREAM])?;
+    w.write_all(&stream.id.to_ne_bytes())?;
+    w.write_all(&[stream.channel as u8])?;
+    w.write_all(&(stream.queue_name.len() as u16).to_ne_bytes())?;
+    w.write_all(stream.queue_name.as_bytes())?;
+    w.write_all(bytes_of(&stream.header))
+}
+
+fn write_message(w: &mut impl Write, stream: u32, msg: &TimingMessage) -> std::io::Result<()> {
+    w.write_all(&[MESSAGE])?;
+    w.write_all(&stream.to_ne_bytes())?;
+    w.write_all(bytes_of(msg))
+}
+
+fn write_dropped(w: &mut impl Write, stream: u32, count: u64) -> std::io::Result<()> {
+    w.write_all(&[DROPPED])?;
+    w.write_all(&stream.to_ne_bytes())?;
+    w.write_all(&count.to_ne_bytes())
+}
+
+/// Consumes the latency and business queues of the chosen timers, writing every message to a capture.
+pub struct Recorder {
+    file:    BufWriter<File>,
+    timers:  Vec<String>,
+    watcher: TimerWatcher,
+    streams: Vec<Stream>,
+}
+
+impl Recorder {
+    /// Records the timers named in `timers`, with all the producers of a shared timer, or every timer if it's empty.
+    /// Overwrites `path`.
+    pub fn create(path: impl AsRef<Path>, timers: Vec<String>) -> std::io::Result<Self> {
+        let mut file = BufWriter::with_capacity(BUFFER_SIZE, File::create(path)?);
+        file.write_all(bytes_of(&CaptureHeader::new()))?;
+        Ok(Self { file,
+                  timers,
+                  watcher: watch_timers()?,
+                  streams: Vec::new() })
+    }
+
+    /// Starts recording the producers that appeared since the last call, and stops recording the ones that are gone
+    pub fn poll_producers(&mut self) -> std::io::Result<()> {
+        for event in self.watcher.poll()? {
+            let info = match event {
+                TimerEvent::Added(info) | TimerEvent::Updated(info) => info,
+                TimerEvent::Removed(queue_name) => {
+                    for id in 0..self.streams.len() {
+                        if self.streams[id].stats.queue_name == queue_name && self.streams[id].consumer.is_some() {
+                            // Whatever the producer sent before leaving is still mapped
+                            while self.record_stream(id)? == BATCH as u64 {}
+                            self.streams[id].consumer = None;
+                        }
+                    }
+                    continue;
+                }
+            };
+            if info.kind != HeaderKind::Timer || !(self.timers.is_empty() || self.timers.iter().any(|t| t == info.name())) {
+                continue;
+            }
+            let Ok(header) = info.header else {
+                continue;
+            };
+            if let Err(e) = header.validate(HeaderKind::Timer) {
+                log::warn!("not recording {}: {e}", info.queue_name);
+                continue;
+            }
+            for channel in [Channel::Latency, Channel::Business] {
+                if self.streams
+                       .iter()
+                       .any(|s| s.consumer.is_some() && s.stats.queue_name == info.queue_name && s.stats.channel == channel)
+                {
+                    continue;
+                }
+                let stream = StreamInfo { id: self.streams.len() as u32,
+                                          queue_name: info.queue_name.clone(),
+                                          channel,
+                                          header };
+                write_stream(&mut self.file, &stream)?;
+                let consumer =
+                    open_consumer(format!("{}/{}{}", crate::QUEUE_DIR, channel.prefix(), info.queue_name));
+                self.streams.push(Stream { stats:    StreamStats { queue_name: stream.queue_name,
+                                                                   channel,
+                                                                   n_messages: 0,
+                                                                   dropped: 0 },
+                                           consumer: Some(consumer),
+                                           seq:      SeqTracker::default() });
+            }
+        }
+        Ok(())
+    }
+
+    fn record_stream(&mut self, id: usize) -> std::io::Result<u64> {
+        let Self { file, streams, .. } = self;
+        let stream = &mut streams[id];
+        let Some(consumer) = stream.consumer.as_mut() else {
+            return Ok(0);
+        };
+        let mut error = None;
+        let mut n = 0;
+        drain(consumer, BATCH, |msg: &TimingMessage| {
+            if error.is_none() {
+                let gap = stream.seq.gap(msg.seq) as u64;
+                stream.stats.dropped += gap;
+                let written = if gap > 0 { write_dropped(file, id as u32, gap) } else { Ok(()) };
+                error = written.and_then(|_| write_message(file, id as u32, msg)).err();
+            }
+            n += 1;
+            true
+        });
+        if let Some(e) = error {
+            return Err(e);
+        }
+        stream.stats.n_messages += n;
+        Ok(n)
+    }
+
+    /// Writes a batch of the messages waiting in each queue, returns how many
+    pub fn record(&mut self) -> std::io::Result<u64> {
+        let mut n = 0;
+        for id in 0..self.streams.len() {
+            n += self.record_stream(id)?;
+        }
+        Ok(n)
+    }
+
+    pub fn n_messages(&self) -> u64 {
+        self.streams.iter().map(|s| s.stats.n_messages).sum()
+    }
+
+    /// Messages missed because a producer sped past the recorder
+    pub fn dropped(&self) -> u64 {
+        self.streams.iter().map(|s| s.stats.dropped).sum()
+    }
+
+    pub fn streams(&self) -> impl Iterator<Item = &StreamStats> {
+        self.streams.iter().map(|s| &s.stats)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Flushes the capture and closes it
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Reads back the [`Record`]s of a capture
+pub struct CaptureReader<R = BufReader<File>> {
+    reader: R,
+    header: CaptureHeader,
+}
+
+impl CaptureReader {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+fn invalid(what: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, what.to_string())
+}
+
+fn read_raw<T: Copy>(reader: &mut impl Read) -> std::io::Result<T> {
+    let mut buf = vec![0; std::mem::size_of::<T>()];
+    reader.read_exact(&mut buf)?;
+    Ok(unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const T) })
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Checks the capture was written by a compatible recorder
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let header: CaptureHeader = read_raw(&mut reader)?;
+        if header.magic != CAPTURE_MAGIC {
+            return Err(invalid("not a ma_timing capture"));
+        }
+        if header.version != CAPTURE_VERSION {
+            return Err(invalid(&format!("capture version {}, expected {CAPTURE_VERSION}", header.version)));
+        }
+        if header.message_size != std::mem::size_of::<TimingMessage>() as u32 {
+            return Err(invalid("timing message size doesn't match"));
+        }
+        if header.queue_header_size != std::mem::size_of::<QueueHeader>() as u32 {
+            return Err(invalid("queue header size doesn't match"));
+        }
+        Ok(Self { reader, header })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    fn read_record(&mut self, tag: u8) -> std::io::Result<Record> {
+        let stream = u32::from_ne_bytes(read_raw(&mut self.reader)?);
+        match tag {
+            STREAM => {
+                let channel = match read_raw::<u8>(&mut self.reader)? {
+                    0 => Channel::Latency,
+                    1 => Channel::Business,
+                    c => return Err(invalid(&format!("unknown channel {c}"))),
+                };
+                let mut name = vec![0; u16::from_ne_bytes(read_raw(&mut self.reader)?) as usize];
+                self.reader.read_exact(&mut name)?;
+                let queue_name = String::from_utf8(name).map_err(|_| invalid("stream name isn't utf-8"))?;
+                Ok(Record::Stream(Box::new(StreamInfo { id: stream,
+                                                        queue_name,
+                                                        channel,
+                                                        header: read_raw(&mut self.reader)? })))
+            }
+            MESSAGE => Ok(Record::Message { stream, msg: read_raw(&mut self.reader)? }),
+            DROPPED => Ok(Record::Dropped { stream, count: u64::from_ne_bytes(read_raw(&mut self.reader)?) }),
+            t => Err(invalid(&format!("unknown record {t}"))),
+        }
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = std::io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tag = [0];
+        match self.reader.read_exact(&mut tag).and_then(|_| self.read_record(tag[0])) {
+            Ok(record) => Some(Ok(record)),
+            // A capture cut off mid-record, e.g. by a crash, ends at its last complete record
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{header::Overhead, payload::Layout};
+
+    #[test]
+    fn read_back_records() {
+        let mut bytes = bytes_of(&CaptureHeader::new()).to_vec();
+        let stream = StreamInfo { id:         0,
+                                  queue_name: "gateway.decode#2".into(),
+                                  channel:    Channel::Business,
+                                  header:     QueueHeader::new(HeaderKind::Timer, Overhead::default(), &Layout::default()) };
+        write_stream(&mut bytes, &stream).unwrap();
+        let msg = TimingMessage { start_t: Instant(10), stop_t: Instant(25), seq: 7, weight: 1, payload: [3; 32] };
+        write_message(&mut bytes, 0, &msg).unwrap();
+        write_dropped(&mut bytes, 0, 4).unwrap();
+        // Cut off mid-message
+        write_message(&mut bytes, 0, &msg).unwrap();
+        bytes.truncate(bytes.len() - 10);
+
+        let reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.header().pid, std::process::id());
+        let records: Vec<Record> = reader.collect::<std::io::Result<_>>().unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(matches!(&records[0], Record::Stream(s) if s.queue_name == "gateway.decode#2"
+                                                        && s.channel == Channel::Business
+                                                        && s.header.pid == std::process::id()));
+        assert!(matches!(&records[1], Record::Message { stream: 0, msg: m }
+                                      if m.stop_t.0 == 25 && m.seq == 7 && m.payload == [3; 32]));
+        assert!(matches!(records[2], Record::Dropped { stream: 0, count: 4 }));
+
+        let mut garbage = bytes.clone();
+        garbage[0] = 0;
+        assert!(CaptureReader::new(garbage.as_slice()).is_err());
+    }
+}
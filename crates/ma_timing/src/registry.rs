@@ -0,0 +1,189 @@
+//! Global registry of thread-local [`Timer`]s, backing the [`timer!`](crate::timer) and
+//! [`timed!`](crate::timed) macros.
+//!
+//! Each thread that uses a name gets its own producer, called `name#pid.0`, `name#pid.1`, ...
+//! The pid keeps them apart from the producers of other processes, and from a plain `Timer`
+//! called `name`. Indices are handed back when a thread exits so thread pools don't keep
+//! creating new queues.
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    sync::Mutex,
+};
+
+use ma_time::{Duration, Instant};
+use once_cell::sync::Lazy;
+
+use crate::{lifecycle, Sampling, Timer};
+
+/// Which per-thread indices are in use for each timer name
+static INDICES: Lazy<Mutex<HashMap<String, Vec<bool>>>> = Lazy::new(Default::default);
+
+fn acquire_index(name: &str) -> usize {
+    let mut indices = INDICES.lock().unwrap();
+    let used = indices.entry(name.to_string()).or_default();
+    match used.iter().position(|u| !u) {
+        Some(id) => {
+            used[id] = true;
+            id
+        }
+        None => {
+            used.push(true);
+            used.len() - 1
+        }
+    }
+}
+
+fn release_index(name: &str, id: usize) {
+    if let Some(used) = INDICES.lock().unwrap().get_mut(name) {
+        used[id] = false;
+    }
+}
+
+/// Name of the queues backing the `id`th thread's producer for `name` in this process
+pub fn thread_queue_name(name: &str, id: usize) -> String {
+    format!("{name}#{}.{id}", std::process::id())
+}
+
+#[derive(Default)]
+struct LocalTimers(HashMap<String, (usize, Box<Timer>)>);
+
+impl Drop for LocalTimers {
+    fn drop(&mut self) {
+        DROPPED.set(true);
+        for (name, (id, _)) in &self.0 {
+            release_index(name, *id);
+        }
+    }
+}
+
+thread_local! {
+    static TIMERS: RefCell<LocalTimers> = RefCell::new(LocalTimers::default());
+    // Set when `TIMERS` is dropped. Without a destructor of its own it can still be read
+    // from the destructors of other thread locals, which may run after that.
+    static DROPPED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Handle to the calling thread's [`Timer`] for a given name.
+///
+/// It is neither `Send` nor `Sync`: the timer it points to lives in the registry of the
+/// thread that created it, and is dropped when that thread exits. From then on, e.g. in
+/// the destructors of other thread locals, the handle does nothing.
+#[derive(Clone, Copy, Debug)]
+pub struct TimerHandle {
+    // Null if the registry was already dropped when the handle was asked for
+    timer: *mut Timer,
+}
+
+impl TimerHandle {
+    #[inline(always)]
+    fn with(self, f: impl FnOnce(&mut Timer)) {
+        if !self.timer.is_null() && !DROPPED.get() {
+            // Safety: the timer is boxed and owned by this thread's registry, which only drops
+            // it along with the registry, and the handle can't leave the thread.
+            f(unsafe { &mut *self.timer })
+        }
+    }
+
+    #[inline(always)]
+    pub fn start(self) {
+        self.with(|t| t.start())
+    }
+
+    #[inline(always)]
+    pub fn stop(self) {
+        self.with(|t| t.stop())
+    }
+
+    pub fn stop_and_latency(self, ingestion_t: Instant) {
+        self.with(|t| t.stop_and_latency(ingestion_t))
+    }
+
+    pub fn latency(self, ingestion_t: Instant) {
+        self.with(|t| t.latency(ingestion_t))
+    }
+
+    pub fn record(self, ingestion_t: Instant, busy: Duration) {
+        self.with(|t| t.record(ingestion_t, busy))
+    }
+
+    pub fn set_sampling(self, sampling: Sampling) {
+        self.with(|t| t.set_sampling(sampling))
+    }
+
+    pub fn set_outlier_threshold(self, threshold: Duration) {
+        self.with(|t| t.set_outlier_threshold(threshold))
+    }
+
+    pub fn set_tag(self, tag: u64) {
+        self.with(|t| t.set_tag(tag))
+    }
+}
+
+/// Returns the calling thread's timer for `name`, creating its queues on first use.
+/// Once the thread's registry is dropped the handle does nothing, see [`TimerHandle`].
+pub fn local(name: &str) -> TimerHandle {
+    TIMERS.try_with(|timers| {
+        let mut timers = timers.borrow_mut();
+        if let Some((_, timer)) = timers.0.get_mut(name) {
+            return TimerHandle { timer: &mut **timer };
+        }
+        lifecycle::check_name(name);
+        let id = acquire_index(name);
+        let timer = Timer::with_queue_name(thread_queue_name(name, id), ());
+        let (_, timer) = timers.0.entry(name.to_string()).or_insert((id, Box::new(timer)));
+        TimerHandle { timer: &mut **timer }
+    })
+    .unwrap_or(TimerHandle { timer: std::ptr::null_mut() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "can't contain '#'")]
+    fn names_with_separator() {
+        local("registry#test");
+    }
+
+    #[test]
+    fn indices_are_reused() {
+        let name = "registry-test";
+        assert_eq!(acquire_index(name), 0);
+        assert_eq!(acquire_index(name), 1);
+        assert_eq!(acquire_index(name), 2);
+        release_index(name, 1);
+        assert_eq!(acquire_index(name), 1);
+        let pid = std::process::id();
+        assert_eq!(thread_queue_name(name, 0), format!("registry-test#{pid}.0"));
+        assert_eq!(thread_queue_name(name, 2), format!("registry-test#{pid}.2"));
+    }
+
+    #[test]
+    fn handles_outlive_registry() {
+        const NAME: &str = "registry-test-late";
+        fn tick() {
+            crate::timer!(NAME).start();
+            crate::timer!(NAME).stop();
+        }
+        struct Late;
+        impl Drop for Late {
+            fn drop(&mut self) {
+                // The cached handles and new ones do nothing rather than touch the dropped timers
+                tick();
+                local("registry-test-later").start();
+            }
+        }
+        thread_local! {
+            static LATE: Late = const { Late };
+        }
+        let thread = std::thread::spawn(|| {
+            // Thread locals are dropped in the reverse order they were first used in
+            LATE.with(|_| {});
+            tick();
+        });
+        thread.join().unwrap();
+        lifecycle::unlink(&thread_queue_name(NAME, 0));
+    }
+}
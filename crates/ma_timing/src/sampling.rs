@@ -0,0 +1,149 @@
+//! Sampling of the calls a [`Timer`](crate::Timer) measures, to bring its cost down on hot paths.
+//!
+//! The sampler is only driven by the live `Timer`.
+#![cfg_attr(feature = "disabled", allow(dead_code))]
+use ma_time::{Duration, Instant};
+
+/// Which calls of a [`Timer`](crate::Timer) actually get measured and sent.
+///
+/// Every sent message carries the number of calls it stands for in
+/// [`TimingMessage::weight`](crate::messages::TimingMessage::weight), which lets the
+/// timekeeper scale throughput back up.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Sampling {
+    /// Measure every call
+    #[default]
+    All,
+    /// Measure every nth call
+    EveryNth(u64),
+    /// Measure each call with the given probability in [0, 1]
+    Probability(f64),
+    /// Measure at most one call per interval
+    Interval(Duration),
+}
+
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+enum Mode {
+    All,
+    EveryNth(u64),
+    // Probability scaled to the full u64 range, compared against a xorshift draw
+    Threshold(u64),
+    Interval(Duration),
+}
+
+/// Decides per call whether to sample, cheap enough to sit in front of `Instant::now()`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub(crate) struct Sampler {
+    mode:    Mode,
+    // Calls skipped since the last sample
+    skipped: u64,
+    // Rng state for `Threshold`, time of the last sample for `Interval`
+    state:   u64,
+    pub(crate) sampled: bool,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new(Sampling::All)
+    }
+}
+
+impl Sampler {
+    pub(crate) fn new(sampling: Sampling) -> Self {
+        let (mode, state) = match sampling {
+            Sampling::All => (Mode::All, 0),
+            Sampling::EveryNth(n) => (Mode::EveryNth(n.max(1)), 0),
+            Sampling::Probability(p) => {
+                let threshold = if p >= 1.0 { u64::MAX } else { (p.max(0.0) * u64::MAX as f64) as u64 };
+                // xorshift state can't be zero
+                (Mode::Threshold(threshold), Instant::now().0 | 1)
+            }
+            Sampling::Interval(interval) => (Mode::Interval(interval), 0),
+        };
+        Self { mode, skipped: 0, state, sampled: true }
+    }
+
+    /// Returns the weight of this call if it should be sampled, i.e. how many calls it represents.
+    #[inline(always)]
+    pub(crate) fn sample(&mut self) -> Option<u64> {
+        self.sampled = match self.mode {
+            Mode::All => true,
+            Mode::EveryNth(n) => self.skipped + 1 >= n,
+            Mode::Threshold(threshold) => {
+                self.state ^= self.state << 13;
+                self.state ^= self.state >> 7;
+                self.state ^= self.state << 17;
+                self.state <= threshold
+            }
+            Mode::Interval(interval) => {
+                // Kept in `state`, see `sampled_at`
+                let now = Instant::now().0;
+                if now.wrapping_sub(self.state) >= interval.0 {
+                    self.state = now;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if self.sampled {
+            let weight = self.skipped + 1;
+            self.skipped = 0;
+            Some(weight)
+        } else {
+            self.skipped += 1;
+            None
+        }
+    }
+
+    /// The clock reading `Interval` took to sample the last call, so the call doesn't have to read it again.
+    #[inline(always)]
+    pub(crate) fn sampled_at(&self) -> Option<Instant> {
+        match self.mode {
+            Mode::Interval(_) => Some(Instant(self.state)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_nth() {
+        let mut sampler = Sampler::new(Sampling::EveryNth(4));
+        let weights: Vec<_> = (0..12).filter_map(|_| sampler.sample()).collect();
+        assert_eq!(weights, vec![4, 4, 4]);
+    }
+
+    #[test]
+    fn interval_reuses_the_clock() {
+        let mut sampler = Sampler::new(Sampling::Interval(Duration(1_000_000_000)));
+        assert_eq!(sampler.sample(), Some(1));
+        let sampled_at = sampler.sampled_at().unwrap();
+        assert!(sampled_at.0 > 0 && sampled_at <= Instant::now());
+        assert_eq!(sampler.sample(), None);
+        assert_eq!(sampler.sampled_at(), Some(sampled_at));
+        assert_eq!(Sampler::new(Sampling::All).sampled_at(), None);
+    }
+
+    #[test]
+    fn weights_add_up() {
+        let mut sampler = Sampler::new(Sampling::Probability(0.1));
+        let n = 100_000;
+        let mut tot = 0;
+        let mut n_sampled = 0;
+        for _ in 0..n {
+            if let Some(w) = sampler.sample() {
+                tot += w;
+                n_sampled += 1;
+            }
+        }
+        // Calls after the last sample are not accounted for yet
+        assert!(tot <= n && tot + 1000 > n);
+        assert!((5_000..15_000).contains(&n_sampled));
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use ma_time::{Duration, Instant};
+
+use crate::registry::{self, TimerHandle};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    // The SharedTimer this thread used last together with its handle, so a thread
+    // hammering on one timer doesn't pay for a registry lookup on each call
+    static LAST_USED: Cell<(usize, Option<TimerHandle>)> = const { Cell::new((0, None)) };
+}
+
+/// A timer that can be shared between threads, e.g. across a thread pool.
+///
+/// Each thread records into its own producer from the [`registry`], called `name#pid.0`,
+/// `name#pid.1`, ..., and the timekeeper merges them back into a single timer, also across
+/// processes. `name` can't contain `#`.
+/// `start` and `stop` pair up per thread. Clones record into the same timer.
+///
+/// ```no_run
+/// let timer = std::sync::Arc::new(ma_timing::SharedTimer::new("pool"));
+/// let handles: Vec<_> = (0..4).map(|_| {
+///     let timer = timer.clone();
+///     std::thread::spawn(move || {
+///         timer.start();
+///         // work
+///         timer.stop();
+///     })
+/// }).collect();
+/// ```
+#[derive(Clone, Debug)]
+pub struct SharedTimer {
+    name: Arc<str>,
+    id:   usize,
+}
+
+impl SharedTimer {
+    pub fn new<S: ToString>(name: S) -> Self {
+        let name = name.to_string();
+        crate::lifecycle::check_name(&name);
+        Self { name: name.into(), id: NEXT_ID.fetch_add(1, Ordering::Relaxed) }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The calling thread's timer, creating it on first use.
+    #[inline]
+    pub fn local(&self) -> TimerHandle {
+        LAST_USED.with(|last| {
+            if let (id, Some(handle)) = last.get() {
+                if id == self.id {
+                    return handle;
+                }
+            }
+            let handle = registry::local(&self.name);
+            last.set((self.id, Some(handle)));
+            handle
+        })
+    }
+
+    #[inline]
+    pub fn start(&self) {
+        self.local().start()
+    }
+
+    #[inline]
+    pub fn stop(&self) {
+        self.local().stop()
+    }
+
+    pub fn stop_and_latency(&self, ingestion_t: Instant) {
+        self.local().stop_and_latency(ingestion_t)
+    }
+
+    pub fn latency(&self, ingestion_t: Instant) {
+        self.local().latency(ingestion_t)
+    }
+
+    pub fn record(&self, ingestion_t: Instant, busy: Duration) {
+        self.local().record(ingestion_t, busy)
+    }
+}
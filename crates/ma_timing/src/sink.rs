@@ -0,0 +1,250 @@
+//! Appends the datapoints of [`TimerSnapshot`]s to CSV or JSON-lines files, e.g. to graph them in a notebook.
+//!
+//! Each row is one complete datapoint of a channel of a timer, e.g. its latency, with its statistics in nanos
+//! and the time it was completed.
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use ma_time::{Duration, Nanos};
+
+use crate::{
+    engine::{Datapoint, TimerSnapshot},
+    stats::Snapshot,
+    utils::format_time,
+};
+
+const TIMESTAMP: &str = "%Y-%m-%dT%H:%M:%S%.6f%:z";
+const CSV_HEADER: &str = "timestamp,timer,channel,count,min,avg,p50,p90,p99,p999,p9999,max,stddev,mad,jitter,dropped,rejected";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkFormat {
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl FromStr for SinkFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::Jsonl),
+            _ => Err(format!("expected csv or jsonl, got {s}")),
+        }
+    }
+}
+
+/// When a sink moves its file aside and starts a new one, whichever comes first.
+/// The old file keeps its path with the time it was opened appended.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// In bytes
+    pub max_size: Option<u64>,
+    pub max_age:  Option<std::time::Duration>,
+}
+
+pub struct SnapshotSink {
+    path:     PathBuf,
+    format:   SinkFormat,
+    rotation: Rotation,
+    file:     BufWriter<File>,
+    size:     u64,
+    opened:   chrono::DateTime<chrono::Local>,
+}
+
+impl SnapshotSink {
+    /// Appends to `path` if it exists.
+    pub fn new(path: impl Into<PathBuf>, format: SinkFormat, rotation: Rotation) -> std::io::Result<Self> {
+        let path = path.into();
+        let (file, size) = Self::open(&path, format)?;
+        Ok(Self { path,
+                  format,
+                  rotation,
+                  file,
+                  size,
+                  opened: chrono::Local::now() })
+    }
+
+    fn open(path: &PathBuf, format: SinkFormat) -> std::io::Result<(BufWriter<File>, u64)> {
+        let mut file = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+        let mut size = file.get_ref().metadata()?.len();
+        if size == 0 && format == SinkFormat::Csv {
+            writeln!(file, "{CSV_HEADER}")?;
+            size += CSV_HEADER.len() as u64 + 1;
+        }
+        Ok((file, size))
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(self.opened.format(".%Y%m%dT%H%M%S%.6f").to_string());
+        std::fs::rename(&self.path, rotated)?;
+        (self.file, self.size) = Self::open(&self.path, self.format)?;
+        self.opened = chrono::Local::now();
+        Ok(())
+    }
+
+    fn needs_rotation(&self) -> bool {
+        self.rotation.max_size.is_some_and(|max| self.size >= max)
+            || self.rotation.max_age
+                   .is_some_and(|max| (chrono::Local::now() - self.opened).to_std().is_ok_and(|age| age >= max))
+    }
+
+    /// Writes a row for each datapoint completed since the previous snapshot, then rotates if needed
+    pub fn write(&mut self, snapshots: &[TimerSnapshot]) -> std::io::Result<()> {
+        for s in snapshots {
+            for (channel, t) in s.channels() {
+                for Datapoint { completed, stats } in &t.new_datapoints {
+                    let row = Row { timestamp: &format_time(*completed, TIMESTAMP),
+                                    timer:     &s.name,
+                                    channel,
+                                    stats,
+                                    dropped:   t.dropped,
+                                    rejected:  t.rejected_below + t.rejected_above };
+                    let line = match self.format {
+                        SinkFormat::Csv => row.csv(),
+                        SinkFormat::Jsonl => row.json(),
+                    };
+                    writeln!(self.file, "{line}")?;
+                    self.size += line.len() as u64 + 1;
+                }
+            }
+        }
+        if self.needs_rotation() {
+            self.rotate()
+        } else {
+            self.file.flush()
+        }
+    }
+}
+
+struct Row<'a> {
+    timestamp: &'a str,
+    timer:     &'a str,
+    channel:   &'a str,
+    stats:     &'a Snapshot,
+    dropped:   usize,
+    rejected:  usize,
+}
+
+impl Row<'_> {
+    fn durations(&self) -> [(&'static str, u64); 11] {
+        let s = self.stats;
+        let nanos = |d: Duration| Nanos::from(d).0;
+        [("min", nanos(s.min)),
+         ("avg", nanos(s.avg)),
+         ("p50", nanos(s.median)),
+         ("p90", nanos(s.p90)),
+         ("p99", nanos(s.p99)),
+         ("p999", nanos(s.p999)),
+         ("p9999", nanos(s.p9999)),
+         ("max", nanos(s.max)),
+         ("stddev", nanos(s.stddev)),
+         ("mad", nanos(s.mad)),
+         ("jitter", nanos(s.jitter))]
+    }
+
+    fn csv(&self) -> String {
+        let quoted = |v: &str| {
+            if v.contains([',', '"', '\n']) {
+                format!("\"{}\"", v.replace('"', "\"\""))
+            } else {
+                v.to_string()
+            }
+        };
+        let mut line = format!("{},{},{},{}", self.timestamp, quoted(self.timer), quoted(self.channel), self.stats.n);
+        for (_, v) in self.durations() {
+            line += &format!(",{v}");
+        }
+        line + &format!(",{},{}", self.dropped, self.rejected)
+    }
+
+    fn json(&self) -> String {
+        let mut line = format!("{{\"timestamp\":\"{}\",\"timer\":{},\"channel\":{},\"count\":{}",
+                               self.timestamp,
+                               json_string(self.timer),
+                               json_string(self.channel),
+                               self.stats.n);
+        for (name, v) in self.durations() {
+            line += &format!(",\"{name}\":{v}");
+        }
+        line + &format!(",\"dropped\":{},\"rejected\":{}}}", self.dropped, self.rejected)
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if c.is_control() => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::TimingData;
+
+    #[test]
+    fn rows_of_new_datapoints() {
+        let dir = std::env::temp_dir().join(format!("ma_timing_sink_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshots.csv");
+        let rotation = Rotation { max_size: Some(CSV_HEADER.len() as u64 + 2), max_age: None };
+        let mut sink = SnapshotSink::new(&path, SinkFormat::Csv, rotation).unwrap();
+
+        // Three complete datapoints of two measurements and an unfinished one between two writes
+        let mut data = TimingData::new(2, 10, Duration::ZERO, 2);
+        for i in 0..7 {
+            data.track_elapsed(Duration(100 + i));
+        }
+        let mut timer = TimerSnapshot { name:      "gateway,decode".into(),
+                                        n_sources: 1,
+                                        latency:   data.snapshot(),
+                                        ..Default::default() };
+        let first = timer.latency.new_datapoints[0].stats;
+        sink.write(&[timer.clone()]).unwrap();
+        timer.latency = data.snapshot();
+        // Nothing new
+        sink.write(&[timer.clone()]).unwrap();
+        let files = || {
+            let mut files: Vec<PathBuf> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+            files.sort();
+            files
+        };
+        // The rows took the file over the size, so it was moved aside
+        assert_eq!(files().len(), 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{CSV_HEADER}\n"));
+        let csv = std::fs::read_to_string(&files()[1]).unwrap();
+        // One row per datapoint, business has none
+        assert_eq!(csv.lines().count(), 4, "{csv}");
+        assert!(csv.lines().skip(1).all(|l| l.contains(",\"gateway,decode\",latency,2,")), "{csv}");
+        // The dispersions are the columns before dropped and rejected
+        let nanos = |d: Duration| Nanos::from(d).0.to_string();
+        let columns: Vec<&str> = csv.lines().nth(1).unwrap().rsplit(',').take(5).collect();
+        assert_eq!(columns[2..], [nanos(first.jitter), nanos(first.mad), nanos(first.stddev)]);
+        let json = Row { timestamp: "", timer: "", channel: "", stats: &first, dropped: 0, rejected: 0 }.json();
+        assert!(json.contains(&format!(",\"mad\":{},\"jitter\":{},", nanos(first.mad), nanos(first.jitter))), "{json}");
+
+        data.track_elapsed(Duration(100));
+        timer.latency = data.snapshot();
+        sink.write(&[timer]).unwrap();
+        assert_eq!(files().len(), 3);
+
+        assert_eq!(json_string("a\"b\n"), "\"a\\\"b\\u000a\"");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+//! Statistics over sets of measured durations, shared by the timekeeper and [`LocalTimer`](crate::LocalTimer).
+use ma_time::Duration;
+
+use crate::histogram::Histogram;
+
+/// Summary statistics of a set of durations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub n:      usize,
+    pub min:    Duration,
+    pub max:    Duration,
+    pub median: Duration,
+    pub avg:    Duration,
+    pub p90:    Duration,
+    pub p99:    Duration,
+    pub p999:   Duration,
+    pub p9999:  Duration,
+    /// Standard deviation
+    pub stddev: Duration,
+    /// Median absolute deviation from the median
+    pub mad:    Duration,
+    /// Mean absolute difference between consecutive measurements
+    pub jitter: Duration,
+}
+
+impl Snapshot {
+    /// The percentiles and the median absolute deviation are within the precision of `histogram`,
+    /// `dispersion` has to have tracked the same measurements.
+    pub fn from_histogram(histogram: &Histogram, dispersion: &Dispersion) -> Self {
+        let [median, p90, p99, p999, p9999] = histogram.percentiles([50.0, 90.0, 99.0, 99.9, 99.99]);
+        Self { n: histogram.len() as usize,
+               min: histogram.min(),
+               max: histogram.max(),
+               median,
+               avg: histogram.mean(),
+               p90,
+               p99,
+               p999,
+               p9999,
+               stddev: dispersion.stddev(),
+               mad: histogram.median_absolute_deviation(),
+               jitter: dispersion.jitter() }
+    }
+
+    /// Applies `f` to the measured durations, e.g. to subtract the clock overhead.
+    /// The dispersions are differences between them and stay as they are.
+    pub fn map(self, f: impl Fn(Duration) -> Duration) -> Self {
+        Self { n:      self.n,
+               min:    f(self.min),
+               max:    f(self.max),
+               median: f(self.median),
+               avg:    f(self.avg),
+               p90:    f(self.p90),
+               p99:    f(self.p99),
+               p999:   f(self.p999),
+               p9999:  f(self.p9999),
+               ..self }
+    }
+}
+
+/// Streaming standard deviation using Welford's updates, and jitter
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Dispersion {
+    n:          u64,
+    mean:       f64,
+    // Sum of squared differences from the mean
+    m2:         f64,
+    last:       Option<Duration>,
+    jitter_sum: u128,
+    n_jitter:   u64,
+}
+
+impl Dispersion {
+    #[inline]
+    pub fn track(&mut self, t: Duration) {
+        self.n += 1;
+        let delta = t.0 as f64 - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (t.0 as f64 - self.mean);
+        if let Some(last) = self.last {
+            self.jitter_sum += t.0.abs_diff(last.0) as u128;
+            self.n_jitter += 1;
+        }
+        self.last = Some(t);
+    }
+
+    /// Population standard deviation
+    pub fn stddev(&self) -> Duration {
+        if self.n == 0 {
+            Duration::ZERO
+        } else {
+            Duration((self.m2 / self.n as f64).sqrt() as u64)
+        }
+    }
+
+    pub fn jitter(&self) -> Duration {
+        if self.n_jitter == 0 {
+            Duration::ZERO
+        } else {
+            Duration((self.jitter_sum / self.n_jitter as u128) as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_of_range() {
+        let (mut histogram, mut dispersion) = (Histogram::new(3), Dispersion::default());
+        for t in (1..=1000).rev().map(Duration) {
+            histogram.record(t);
+            dispersion.track(t);
+        }
+        let s = Snapshot::from_histogram(&histogram, &dispersion);
+        assert_eq!(s.n, 1000);
+        assert_eq!((s.min, s.max, s.median), (Duration(1), Duration(1000), Duration(500)));
+        assert_eq!(s.avg, Duration(500));
+        assert_eq!((s.p90, s.p99, s.p999, s.p9999), (Duration(900), Duration(990), Duration(999), Duration(1000)));
+        assert_eq!((s.stddev, s.mad, s.jitter), (Duration(288), Duration(250), Duration(1)));
+        assert_eq!(Snapshot::from_histogram(&Histogram::new(3), &Dispersion::default()), Snapshot::default());
+    }
+}
@@ -1,8 +1,10 @@
-use std::{fmt::Debug, io::stdout};
+use std::{
+    collections::{HashMap, HashSet},
+    io::stdout,
+};
 
 use core_affinity::CoreId;
 use crossterm::event::{self, KeyCode, KeyEventKind};
-use ma_queues::{Consumer, ReadError};
 use ma_time::*;
 use ratatui::{
     prelude::*,
@@ -10,141 +12,77 @@ use ratatui::{
     Terminal,
 };
 
-use crate::{messages::TimingMessage, utils::CircularBuffer};
-//TODO: Have tuple of 2 timingdatas pls
-/// Keep track of msg latencies
-/// All in nanos
-#[derive(Debug, Clone)]
-pub struct TimingData {
-    title:        String,
-    // These are updated when Start
-    measurements: Vec<Duration>,
-    averages:     CircularBuffer<Duration>,
-
-    min:    Duration,
-    max:    Duration,
-    median: Duration,
-
-    clock_overhead: Duration,
-
-    samples_per_datapoint: usize,
-    n_messages:            usize,
-    last_report:           Instant,
-}
-
-impl TimingData {
-    pub fn new(title: String, samples_per_datapoint: usize, n_datapoints: usize, clock_overhead: Duration) -> Self {
-        let measurements = Vec::with_capacity(samples_per_datapoint);
-        let averages = CircularBuffer::new(n_datapoints);
-
-        Self { title,
-               measurements,
-               averages,
-               min: Duration(0),
-               max: Duration(0),
-               median: Duration(0),
-               clock_overhead,
-               samples_per_datapoint,
-               n_messages: 0,
-               last_report: Instant::now() }
-    }
-
-    fn min(&self) -> (Duration, usize) {
-        let (mut m, mut minid) = (Duration::MAX, 0);
-        for (id, v) in self.averages.iter().enumerate() {
-            if *v < m {
-                m = *v;
-                minid = id;
-            }
-        }
-        (m, minid)
-    }
-
-    fn max(&self) -> (Duration, usize) {
-        let (mut m, mut maxid) = (Duration::ZERO, 0);
-        for (id, v) in self.averages.iter().enumerate() {
-            if *v > m {
-                m = *v;
-                maxid = id;
-            }
-        }
-        (m, maxid)
-    }
+pub use crate::engine::{Bounds, TimingData};
+use crate::{
+    engine::{self, Outlier, PerfStats, SnapshotKind, StatsEngine, TimerSnapshot, TimingSnapshot},
+    header::QueueHeader,
+    prometheus::MetricsServer,
+    sink::SnapshotSink,
+    stats::Snapshot,
+    utils::{format_time, CircularBuffer},
+};
 
-    // Also sets last min last max
-    fn cur_avg(&self) -> Duration {
-        let n = self.measurements.len();
-        if n == 0 {
-            Duration::ZERO
-        } else {
-            self.corrected_or_zero(self.measurements.iter().sum::<Duration>() / n)
-        }
-    }
+/// Outliers of a timer the terminal UI keeps, the latest ones
+const N_OUTLIERS: usize = 16;
 
-    fn avg(&self) -> Duration {
-        let n = self.averages.len();
-        if n == 0 {
-            Duration::ZERO
-        } else {
-            let tot: Duration = self.averages.iter().sum();
-            tot / n as u64
-        }
-    }
+/// The latency or business measurements of a timer as shown, with the averages of its latest datapoints
+struct Channel {
+    title:    &'static str,
+    // As measured, corrected when shown
+    averages: CircularBuffer<Duration>,
+}
 
-    fn corrected_or_zero(&self, t: Duration) -> Duration {
-        t.saturating_sub(self.clock_overhead)
+impl Channel {
+    fn new(title: &'static str, n_datapoints: usize) -> Self {
+        Self { title, averages: CircularBuffer::new(n_datapoints) }
     }
 
-    fn register_datapoint(&mut self) {
-        let n = self.measurements.len();
-        if n == 0 {
-            return;
+    fn update(&mut self, s: &TimingSnapshot) {
+        for d in &s.new_datapoints {
+            self.averages.push(d.stats.avg);
         }
-        self.measurements.sort();
-
-        self.max = self.corrected_or_zero(*self.measurements.last().unwrap());
-        self.min = self.corrected_or_zero(*self.measurements.first().unwrap());
-
-        self.median = self.corrected_or_zero(self.measurements[n / 2]);
-        self.averages.push(self.cur_avg());
-        self.measurements.clear();
     }
 
-    fn track(&mut self, el: Duration) -> bool {
-        // if el < self.minimum_duration || el > Duration(1000) {
-        //     return false
-        // }
-        self.n_messages += 1;
-        self.measurements.push(el);
-        if self.measurements.len() == self.samples_per_datapoint {
-            self.register_datapoint();
-            true
+    fn report(&self, name: &str, s: &TimingSnapshot, show_corrected: bool, frame: &mut Frame, rect: Rect) {
+        let values = if !show_corrected {
+            "raw".to_string()
+        } else if s.clock_overhead == Duration::ZERO {
+            "no overhead to correct by".to_string()
         } else {
-            false
-        }
-    }
-
-    pub fn report(&mut self, name: &str, frame: &mut Frame, rect: Rect) {
-        self.register_datapoint();
-
-        let avg = self.averages.last();
-        let text: Vec<Line> = vec![format!("{} Report for {name}", self.title).into(),
-                                   format!("Statistics for last datapoint with {} msgs ({} msg/s):",
-                                           self.n_messages,
-                                           self.n_messages as f64 / self.last_report.elapsed().as_secs()).into(),
-                                   format!("avg: {avg} - median: {} - min: {} - max: {}",
-                                           self.median, self.min, self.max).into(),].into();
+            format!("corrected by {}", s.clock_overhead)
+        };
+        let overhead = if show_corrected { s.clock_overhead } else { Duration::ZERO };
+        let shown = if show_corrected { s.corrected() } else { s.clone() };
+        let which = if shown.datapoints == 0 { "unfinished" } else { "last" };
+        let datapoint = shown.latest();
+        let mut text: Vec<Line> = vec![format!("{} Report for {name} ({values})", self.title).into(),
+                                       format!("Statistics for {which} datapoint with {} msgs ({} msg/s, {:.1}% sampled):",
+                                               datapoint.n,
+                                               shown.throughput,
+                                               shown.sampled * 100.0).into(),
+                                       percentiles_line(datapoint).into(),
+                                       format!("{} - dropped: {}", dispersion_line(datapoint), shown.dropped).into(),
+                                       format!("Cumulative over {} msgs:", shown.cumulative.n).into(),
+                                       percentiles_line(&shown.cumulative).into(),
+                                       dispersion_line(&shown.cumulative).into(),];
+        text.extend(rejected_line(s).map(Line::from));
 
         let sub_layout = Layout::new().direction(Direction::Vertical)
                                       .constraints([Constraint::Min(text.len() as u16), Constraint::Min(20)])
                                       .split(rect);
 
         frame.render_widget(Paragraph::new(text), sub_layout[0]);
-        let to_plot: Vec<(f64, f64)> = self.averages.iter().enumerate().map(|(i, &p)| (i as f64, p.0 as f64)).collect();
+        self.render_chart(overhead, frame, sub_layout[1]);
+    }
+
+    fn render_chart(&self, overhead: Duration, frame: &mut Frame, rect: Rect) {
+        let corrected = |t: Duration| t.saturating_sub(overhead);
+        let to_plot: Vec<(f64, f64)> =
+            self.averages.iter().enumerate().map(|(i, &p)| (i as f64, corrected(p).0 as f64)).collect();
 
         let def = Duration::default();
-        let min = self.averages.iter().min().unwrap_or_else(|| &def);
-        let max = self.averages.iter().max().unwrap_or_else(|| &def);
+        let min = &corrected(*self.averages.iter().min().unwrap_or_else(|| &def));
+        let max = &corrected(*self.averages.iter().max().unwrap_or_else(|| &def));
         let ylabels = vec![format!("{min}").into(), format!("{max}").into()];
 
         let xlabels = vec![format!("0").into(), format!("{}", self.averages.len()).into(),];
@@ -155,67 +93,421 @@ impl TimingData {
         let yaxis = Axis::default().bounds([min.0 as f64, max.0 as f64])
                                    .style(Style::default().fg(Color::LightBlue))
                                    .labels(ylabels.into());
+        let avg = match self.averages.len() {
+            0 => Duration::ZERO,
+            n => self.averages.iter().sum::<Duration>() / n as u64,
+        };
         let chart =
             Chart::new(vec![Dataset::default().name(format!("{} averages", self.title)).data(&to_plot)]).x_axis(xaxis)
                                                                                                         .y_axis(yaxis);
         frame.render_widget(chart.block(Block::new().borders(Borders::ALL)
-                                                    .title(format!("Running avg: {}", self.avg()))),
-                            sub_layout[1]);
-        self.n_messages = 0;
-        self.last_report = Instant::now();
+                                                    .title(format!("Running avg: {}", corrected(avg)))),
+                            rect);
+    }
+}
+
+fn percentiles_line(s: &Snapshot) -> String {
+    format!("avg: {} - p50: {} - p90: {} - p99: {} - p99.9: {} - p99.99: {} - max: {}",
+            s.avg, s.median, s.p90, s.p99, s.p999, s.p9999, s.max)
+}
+
+fn dispersion_line(s: &Snapshot) -> String {
+    format!("stddev: {} - MAD: {} - jitter: {}", s.stddev, s.mad, s.jitter)
+}
+
+fn rejected_line(s: &TimingSnapshot) -> Option<String> {
+    if s.bounds.is_unbounded() {
+        return None;
     }
+    let min = s.bounds.min.map_or(Duration::ZERO, Duration::from);
+    let max = if s.max_accepted == Duration::MAX { String::new() } else { s.max_accepted.to_string() };
+    Some(format!("bounds {} ({min}..{max}) - rejected below: {} above: {}",
+                 s.bounds, s.rejected_below, s.rejected_above))
 }
 
-struct TimerData {
-    name:          String,
-    latency_data:  TimingData,
-    business_data: TimingData,
-    direction:     Direction,
+/// Summary of the most frequent payload groups since the previous snapshot
+fn payload_lines(s: &TimerSnapshot, max_groups: usize) -> Vec<Line<'static>> {
+    let Some(field) = &s.group_by else {
+        return Vec::new();
+    };
+    let mut groups: Vec<_> = s.payload_groups.iter().collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.latency.n + g.business.n));
+
+    let mut text: Vec<Line> = vec![format!("Grouped by {field} ({} values):", groups.len()).into()];
+    for g in groups.iter().take(max_groups) {
+        text.push(format!("{field}={}: latency {} msgs avg: {} max: {} | business {} msgs avg: {} max: {}",
+                          g.value,
+                          g.latency.n,
+                          g.latency.avg(),
+                          g.latency.max,
+                          g.business.n,
+                          g.business.avg(),
+                          g.business.max).into());
+    }
+    text
 }
-impl TimerData {
-    pub fn new(name: String, samples_per_datapoint: usize, n_datapoints: usize, clock_overhead: Duration) -> Self {
-        Self { name,
-               latency_data: TimingData::new("Latency".into(), samples_per_datapoint, n_datapoints, clock_overhead),
-               business_data: TimingData::new("Business".into(), samples_per_datapoint, n_datapoints, clock_overhead),
-               direction: Direction::Horizontal }
+
+impl PerfStats {
+    fn report(&self) -> Option<Line<'static>> {
+        if self.n == 0 {
+            return None;
+        }
+        let avg = |v: u64| v as f64 / self.n as f64;
+        let mut line = format!("Perf per call ({} calls): context switches avg: {:.3} max: {} - page faults avg: {:.3} max: {}",
+                               self.n,
+                               avg(self.total.context_switches),
+                               self.max.context_switches,
+                               avg(self.total.page_faults),
+                               self.max.page_faults);
+        if self.hardware {
+            let ipc = self.total.instructions as f64 / self.total.cycles.max(1) as f64;
+            line += &format!(" - instructions avg: {:.0} - cycles avg: {:.0} max: {} - IPC: {ipc:.2}",
+                             avg(self.total.instructions),
+                             avg(self.total.cycles),
+                             self.max.cycles);
+        } else {
+            line += " - no hardware counters";
+        }
+        Some(line.into())
     }
+}
 
-    pub fn report(&mut self, frame: &mut Frame, rect: Rect) {
-        let layout = Layout::new().direction(self.direction)
+impl Outlier {
+    fn line(&self) -> Line<'static> {
+        let elapsed = Nanos::from(self.msg.msg.elapsed());
+        format!("{} -> {} ({}) {} seq: {} cpu: {} tag: {}",
+                format_time(self.stop.saturating_sub(elapsed), "%H:%M:%S%.6f"),
+                format_time(self.stop, "%H:%M:%S%.6f"),
+                elapsed,
+                if self.msg.latency { "latency" } else { "business" },
+                self.msg.msg.seq,
+                self.msg.cpu,
+                self.msg.tag).into()
+    }
+}
+
+fn header_line(header: &QueueHeader) -> Line<'static> {
+    let overhead = header.overhead;
+    format!("Producer {} (pid {}) created {}, format v{}, TSC {:.3} GHz, start/stop overhead min: {} p50: {} p99: {} max: {}",
+            header.exe(),
+            header.pid,
+            format_time(Nanos(header.created), "%Y-%m-%d %H:%M:%S"),
+            header.version,
+            header.tsc_frequency as f64 / 1e9,
+            Duration(overhead.min),
+            Duration(overhead.median),
+            Duration(overhead.p99),
+            Duration(overhead.max)).into()
+}
+
+/// What the terminal UI keeps of a timer, group or pipeline across snapshots
+struct History {
+    /// End-to-end for a pipeline
+    latency:  Channel,
+    business: Channel,
+    outliers: CircularBuffer<Outlier>,
+}
+
+impl History {
+    fn new(kind: SnapshotKind, n_datapoints: usize) -> Self {
+        let latency = if kind == SnapshotKind::Pipeline { "End-to-end" } else { "Latency" };
+        Self { latency:  Channel::new(latency, n_datapoints),
+               business: Channel::new("Business", n_datapoints),
+               outliers: CircularBuffer::new(N_OUTLIERS), }
+    }
+
+    fn update(&mut self, s: &TimerSnapshot) {
+        self.latency.update(&s.latency);
+        self.business.update(&s.business);
+        for o in &s.outliers {
+            self.outliers.push(*o);
+        }
+    }
+
+    fn report_timer(&self, s: &TimerSnapshot, show_corrected: bool, frame: &mut Frame, rect: Rect, direction: Direction) {
+        let mut text: Vec<Line> = s.header.iter().map(header_line).collect();
+        text.extend(s.perf.report());
+        text.extend(payload_lines(s, 10));
+        let rect = if text.is_empty() {
+            rect
+        } else {
+            let outer = Layout::new().direction(Direction::Vertical)
+                                     .constraints([Constraint::Min(text.len() as u16), Constraint::Percentage(80)])
+                                     .split(rect);
+            frame.render_widget(Paragraph::new(text), outer[0]);
+            outer[1]
+        };
+        let rect = if self.outliers.len() == 0 {
+            rect
+        } else {
+            let mut lines: Vec<Line> = self.outliers.iter().map(|o| o.line()).collect();
+            // Most recent first
+            lines.reverse();
+            lines.truncate(10);
+            let outer = Layout::new().direction(Direction::Vertical)
+                                     .constraints([Constraint::Min(0), Constraint::Length(lines.len() as u16 + 2)])
+                                     .split(rect);
+            let block = Block::new().title(format!("Outliers ({} total)", s.n_outliers)).borders(Borders::ALL);
+            frame.render_widget(Paragraph::new(lines).block(block), outer[1]);
+            outer[0]
+        };
+        let name = if s.n_sources > 1 { format!("{} ({} producers)", s.name, s.n_sources) } else { s.name.clone() };
+        self.report_channels(&name, s, show_corrected, frame, rect, direction);
+    }
+
+    fn report_group(&self, s: &TimerSnapshot, show_corrected: bool, frame: &mut Frame, rect: Rect, direction: Direction) {
+        let name = format!("{} ({} timers)", s.name, s.n_sources);
+        self.report_channels(&name, s, show_corrected, frame, rect, direction);
+    }
+
+    fn report_channels(&self,
+                       name: &str,
+                       s: &TimerSnapshot,
+                       show_corrected: bool,
+                       frame: &mut Frame,
+                       rect: Rect,
+                       direction: Direction) {
+        let layout = Layout::new().direction(direction)
                                   .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
                                   .split(rect);
-        self.latency_data.report(&self.name, frame, layout[0]);
-        self.business_data.report(&self.name, frame, layout[1]);
+        self.latency.report(name, &s.latency, show_corrected, frame, layout[0]);
+        self.business.report(name, &s.business, show_corrected, frame, layout[1]);
     }
 
-    pub fn track_latency(&mut self, msg: &TimingMessage) -> bool {
-        self.latency_data.track(msg.elapsed())
+    fn report_pipeline(&self, s: &TimerSnapshot, show_corrected: bool, frame: &mut Frame, rect: Rect) {
+        let mut text: Vec<Line> = s.header.iter().map(header_line).collect();
+        text.push(format!("Pipeline breakdown for {}", s.name).into());
+        for ((name, stage), (_, cumulative)) in s.stages.iter().zip(&s.cumulative_stages) {
+            let (stage, cumulative) = (stage.latest(), cumulative.latest());
+            text.push(format!("{name}: stage avg: {} - median: {} - max: {} | cumulative avg: {} - median: {} - max: {}",
+                              stage.avg,
+                              stage.median,
+                              stage.max,
+                              cumulative.avg,
+                              cumulative.median,
+                              cumulative.max).into());
+        }
+        let layout = Layout::new().direction(Direction::Vertical)
+                                  .constraints([Constraint::Min(text.len() as u16), Constraint::Min(20)])
+                                  .split(rect);
+        frame.render_widget(Paragraph::new(text), layout[0]);
+        self.latency.report(&s.name, &s.latency, show_corrected, frame, layout[1]);
     }
+}
 
-    pub fn track_business(&mut self, msg: &TimingMessage) -> bool {
-        self.business_data.track(msg.elapsed())
-    }
+/// A row of the Timers panel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Row {
+    Group(usize),
+    Timer(usize),
+    Pipeline(usize),
+}
+
+/// Whether `name` is below `group` in the hierarchy of dot-separated names
+fn in_group(name: &str, group: &str) -> bool {
+    name.len() > group.len() && name.starts_with(group) && name.as_bytes()[group.len()] == b'.'
 }
 
-fn black_box<T>(dummy: T) -> T {
-    unsafe { std::ptr::read_volatile(&dummy) }
+/// Timers and groups as a tree of their names, each row with its depth, followed by the pipelines.
+/// The rows below `collapsed` groups are left out.
+fn tree(timer_names: &[&str], group_names: &[&str], n_pipelines: usize, collapsed: &HashSet<String>) -> Vec<(usize, Row)> {
+    let mut rows: Vec<(&str, Row)> = group_names.iter()
+                                                .enumerate()
+                                                .map(|(i, name)| (*name, Row::Group(i)))
+                                                .chain(timer_names.iter().enumerate().map(|(i, name)| (*name, Row::Timer(i))))
+                                                .collect();
+    // A timer named like a group comes before it rather than among its children
+    rows.sort_by(|(a, row_a), (b, row_b)| {
+            a.split('.').cmp(b.split('.')).then(matches!(row_a, Row::Group(_)).cmp(&matches!(row_b, Row::Group(_))))
+        });
+    rows.into_iter()
+        .filter(|(name, _)| !collapsed.iter().any(|group| in_group(name, group)))
+        .map(|(name, row)| (name.matches('.').count(), row))
+        .chain((0..n_pipelines).map(|i| (0, Row::Pipeline(i))))
+        .collect()
 }
 
-pub fn clock_overhead() -> Duration {
-    let start = Instant::now();
-    for _ in 0..1_000_000 {
-        black_box(Instant::now());
+/// The rows of the Timers panel with their depth, see [`tree`]
+fn rows<'a>(snapshots: &'a [TimerSnapshot], collapsed: &HashSet<String>) -> Vec<(usize, &'a TimerSnapshot)> {
+    let of_kind = |kind: SnapshotKind| -> Vec<&TimerSnapshot> { snapshots.iter().filter(|s| s.kind == kind).collect() };
+    let (timers, groups, pipelines) = (of_kind(SnapshotKind::Timer), of_kind(SnapshotKind::Group), of_kind(SnapshotKind::Pipeline));
+    let names = |snapshots: &[&'a TimerSnapshot]| -> Vec<&'a str> { snapshots.iter().map(|s| s.name.as_str()).collect() };
+    tree(&names(&timers), &names(&groups), pipelines.len(), collapsed).into_iter()
+                                                                      .map(|(depth, row)| {
+                                                                          let s = match row {
+                                                                              Row::Group(i) => groups[i],
+                                                                              Row::Timer(i) => timers[i],
+                                                                              Row::Pipeline(i) => pipelines[i],
+                                                                          };
+                                                                          (depth, s)
+                                                                      })
+                                                                      .collect()
+}
+
+/// What the terminal UI shows, changed with the keys
+struct View {
+    n_datapoints:   usize,
+    histories:      HashMap<(SnapshotKind, String), History>,
+    // Names of the groups whose timers are hidden
+    collapsed:      HashSet<String>,
+    // How latency and business timings are stacked, toggled with `s`
+    direction:      Direction,
+    // Whether the clock overhead is subtracted, toggled with `o`
+    show_corrected: bool,
+    // Index of the payload field each timer is grouped by, cycled with `g`
+    group_by:       HashMap<String, Option<usize>>,
+    curid:          usize,
+}
+
+impl View {
+    fn new(n_datapoints: usize) -> Self {
+        Self { n_datapoints,
+               histories: HashMap::new(),
+               collapsed: HashSet::new(),
+               direction: Direction::Horizontal,
+               show_corrected: true,
+               group_by: HashMap::new(),
+               curid: 0 }
+    }
+
+    fn update(&mut self, snapshots: &[TimerSnapshot]) {
+        for s in snapshots {
+            self.histories
+                .entry((s.kind, s.name.clone()))
+                .or_insert_with(|| History::new(s.kind, self.n_datapoints))
+                .update(s);
+        }
+    }
+
+    /// Returns whether `key` changed anything
+    fn handle(&mut self, key: KeyCode, engine: &mut StatsEngine, snapshots: &[TimerSnapshot]) -> bool {
+        let rows = rows(snapshots, &self.collapsed);
+        let selected = rows.get(self.curid).map(|(_, s)| *s);
+        match key {
+            KeyCode::Char('s') => {
+                self.direction = match self.direction {
+                    Direction::Horizontal => Direction::Vertical,
+                    Direction::Vertical => Direction::Horizontal,
+                };
+            }
+            KeyCode::Char('o') => self.show_corrected = !self.show_corrected,
+            KeyCode::Char('b') => {
+                if let Some(s) = selected {
+                    let mut bounds = engine.bounds_of(&s.name);
+                    bounds.toggle_auto();
+                    engine.set_bounds(s.name.clone(), bounds);
+                }
+            }
+            KeyCode::Char('g') => {
+                // Groups by the next field of the payload, or stops grouping after the last one
+                if let Some(s) = selected.filter(|s| s.kind == SnapshotKind::Timer) {
+                    let fields = &s.layout.fields;
+                    let group_by = self.group_by.entry(s.name.clone()).or_default();
+                    *group_by = match *group_by {
+                        None if !fields.is_empty() => Some(0),
+                        Some(i) if i + 1 < fields.len() => Some(i + 1),
+                        _ => None,
+                    };
+                    engine.set_group_by(&s.name, group_by.map(|i| fields[i].name.as_str()));
+                }
+            }
+            KeyCode::Enter | KeyCode::Left | KeyCode::Right => {
+                if let Some(s) = selected.filter(|s| s.kind == SnapshotKind::Group) {
+                    let collapse = match key {
+                        KeyCode::Left => true,
+                        KeyCode::Right => false,
+                        _ => !self.collapsed.contains(&s.name),
+                    };
+                    if collapse {
+                        self.collapsed.insert(s.name.clone());
+                    } else {
+                        self.collapsed.remove(&s.name);
+                    }
+                }
+            }
+            KeyCode::Down => {
+                self.curid += 1;
+                if self.curid >= rows.len() {
+                    self.curid = 0;
+                }
+            }
+            KeyCode::Up => {
+                if self.curid == 0 {
+                    self.curid = rows.len().saturating_sub(1);
+                } else {
+                    self.curid -= 1;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn draw(&self, frame: &mut Frame, snapshots: &[TimerSnapshot], rejected: &[(String, String)]) {
+        let layout = Layout::default().direction(Direction::Horizontal)
+                                      .constraints([Constraint::Percentage(20), Constraint::Percentage(80)])
+                                      .split(frame.size());
+
+        let rows = rows(snapshots, &self.collapsed);
+        let last = |name: &str| name.rsplit('.').next().unwrap_or(name).to_string();
+        let mut namelist: Text = Vec::from_iter(rows.iter().enumerate().map(|(i, (depth, s))| {
+                                                                       let indent = "  ".repeat(*depth);
+                                                                       let label = match s.kind {
+                                                                           SnapshotKind::Group => {
+                                                                               let marker = if self.collapsed.contains(&s.name) {
+                                                                                   "▸"
+                                                                               } else {
+                                                                                   "▾"
+                                                                               };
+                                                                               format!("{indent}{marker} {}", last(&s.name))
+                                                                           }
+                                                                           SnapshotKind::Timer => format!("{indent}{}", last(&s.name)),
+                                                                           SnapshotKind::Pipeline => s.name.clone(),
+                                                                       };
+                                                                       if i == self.curid {
+                                                                           Span::styled(label, Style::default().bg(Color::Gray)).into()
+                                                                       } else {
+                                                                           Span::raw(label).into()
+                                                                       }
+                                                                   })).into();
+        namelist.extend(rejected.iter().map(|(name, reason)| {
+                                           Line::styled(format!("{name}: {reason}"), Style::default().fg(Color::Red))
+                                       }));
+
+        frame.render_widget(Paragraph::new(namelist).block(Block::new().title("Timers").borders(Borders::ALL)), layout[0]);
+        let Some((_, s)) = rows.get(self.curid) else {
+            return;
+        };
+        let Some(history) = self.histories.get(&(s.kind, s.name.clone())) else {
+            return;
+        };
+        match s.kind {
+            SnapshotKind::Group => history.report_group(s, self.show_corrected, frame, layout[1], self.direction),
+            SnapshotKind::Timer => history.report_timer(s, self.show_corrected, frame, layout[1], self.direction),
+            SnapshotKind::Pipeline => history.report_pipeline(s, self.show_corrected, frame, layout[1]),
+        }
     }
-    let end = Instant::now();
-    Duration((end.0 - start.0) / 1_000_000)
 }
 
-//TODO: Have a built in threshold to throw out timing messages that mean nothing
+/// Where [`TimeKeeper::execute_headless`] writes its reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeadlessOutput {
+    Stdout,
+    /// At info level
+    Log,
+}
+
+/// Terminal UI showing the statistics of a [`StatsEngine`].
+///
+/// The sinks, metrics and headless tables get the statistics without the clock overhead,
+/// see [`TimerSnapshot::corrected`]. Press `o` to toggle it on screen.
 pub struct TimeKeeper {
-    core:                  CoreId,
-    report_interval:       std::time::Duration,
-    samples_per_datapoint: usize,
-    n_datapoints:          usize,
+    core:            CoreId,
+    report_interval: std::time::Duration,
+    n_datapoints:    usize,
+    engine:          StatsEngine,
+    sinks:           Vec<SnapshotSink>,
+    metrics:         Option<MetricsServer>,
 }
 
 impl TimeKeeper {
@@ -224,158 +516,139 @@ impl TimeKeeper {
                samples_per_datapoint: usize,
                n_datapoints: usize)
                -> Self {
-        Self { core, report_interval, samples_per_datapoint, n_datapoints }
+        Self { core,
+               report_interval,
+               n_datapoints,
+               engine: StatsEngine::new(samples_per_datapoint, n_datapoints).expect("couldn't watch queue directory"),
+               sinks: Vec::new(),
+               metrics: None }
+    }
+
+    /// Also writes the snapshots of all timers to `sink` every report interval
+    pub fn add_sink(&mut self, sink: SnapshotSink) {
+        self.sinks.push(sink);
+    }
+
+    /// Serves the snapshots of all timers to Prometheus, updated every report interval
+    pub fn set_metrics_server(&mut self, server: MetricsServer) {
+        self.metrics = Some(server);
+    }
+
+    /// See [`StatsEngine::set_filters`]
+    pub fn set_filters(&mut self, filters: Vec<(String, String)>) {
+        self.engine.set_filters(filters);
+    }
+
+    /// See [`StatsEngine::set_significant_digits`]
+    pub fn set_significant_digits(&mut self, significant_digits: u8) {
+        self.engine.set_significant_digits(significant_digits);
+    }
+
+    /// Bounds of the timers, groups and pipelines without their own, see [`set_bounds`](TimeKeeper::set_bounds).
+    pub fn set_default_bounds(&mut self, bounds: Bounds) {
+        self.engine.set_default_bounds(bounds);
+    }
+
+    /// Only track the measurements of the timer, group or pipeline `name` within `bounds`.
+    /// Press `b` to toggle the auto bound of the selected one.
+    pub fn set_bounds(&mut self, name: String, bounds: Bounds) {
+        self.engine.set_bounds(name, bounds);
+    }
+
+    /// Writes a table of all timers every report interval rather than showing the terminal UI,
+    /// see [`format_table`](engine::format_table). Returns after `duration` if there is one.
+    pub fn execute_headless(&mut self, output: HeadlessOutput, duration: Option<std::time::Duration>) {
+        core_affinity::set_for_current(self.core);
+        let start = std::time::Instant::now();
+        loop {
+            self.engine.poll_producers().expect("couldn't scan queue directory");
+            let curt = std::time::Instant::now();
+            while curt.elapsed() < self.report_interval {
+                self.engine.consume();
+            }
+            let snapshots: Vec<TimerSnapshot> = self.engine.snapshots().iter().map(TimerSnapshot::corrected).collect();
+            publish(&mut self.sinks, self.metrics.as_ref(), &snapshots);
+            let table = engine::format_table(&snapshots);
+            match output {
+                HeadlessOutput::Stdout => println!("{table}"),
+                HeadlessOutput::Log => log::info!("\n{table}"),
+            }
+            if duration.is_some_and(|d| start.elapsed() >= d) {
+                return;
+            }
+        }
     }
 
     pub fn execute(&mut self) {
         core_affinity::set_for_current(self.core);
-        let clock_overhead = clock_overhead();
 
-        // let mut names = Vec::new();
-        let mut time_datas: Vec<TimerData> = Vec::new();
-        let mut latency_consumers = Vec::new();
-        let mut business_consumers = Vec::new();
         let rep_interval = self.report_interval;
+        let mut view = View::new(self.n_datapoints);
+        let Self { engine, sinks, metrics, .. } = self;
 
         let mut terminal = Terminal::new(CrosstermBackend::new(stdout())).unwrap();
-        let mut curid = 0;
-        let mut stacking_direction = Direction::Vertical;
+        // Taken once per report interval, so the screen, sinks and metrics agree
+        let mut snapshots = Vec::new();
         terminal.clear();
 
         loop {
-            for entry in std::fs::read_dir(super::QUEUE_DIR).unwrap().into_iter().filter_map(|e| e.ok()) {
-                let name = entry.path().as_os_str().to_str().unwrap().to_string();
-                if name.contains("latency") {
-                    let (dir, real_name) = name.split_once("latency-").unwrap();
-
-                    if time_datas.iter().find(|d| d.name == real_name).is_none() {
-                        let d = TimerData::new(real_name.to_string().clone(),
-                                               self.samples_per_datapoint,
-                                               self.n_datapoints,
-                                               clock_overhead);
-                        time_datas.push(d);
-                        let latency_q =
-                            ma_queues::Queue::shared(format!("{}/latency-{real_name}", crate::QUEUE_DIR),
-                                                     crate::QUEUE_SIZE,
-                                                     ma_queues::QueueType::SPMC).expect("couldn't open latency queue");
-                        latency_consumers.push(Consumer::from(latency_q));
-                        let business_q =
-                            ma_queues::Queue::shared(format!("{}/timing-{real_name}", crate::QUEUE_DIR),
-                                                     crate::QUEUE_SIZE,
-                                                     ma_queues::QueueType::SPMC).expect("couldn't open timing queue");
-                        business_consumers.push(Consumer::from(business_q));
-                    }
-                }
-            }
+            engine.poll_producers().expect("couldn't scan queue directory");
             let curt = std::time::Instant::now();
             while curt.elapsed() < rep_interval {
-                handle_latency_messages(&mut time_datas, &mut latency_consumers, self.samples_per_datapoint);
-                handle_business_messages(&mut time_datas, &mut business_consumers, self.samples_per_datapoint);
+                engine.consume();
                 if event::poll(std::time::Duration::ZERO).unwrap() {
                     if let event::Event::Key(key) = event::read().unwrap() {
                         if matches!(key.kind, KeyEventKind::Press) {
-                            match key.code {
-                                KeyCode::Char('q') => return,
-                                KeyCode::Char('s') => {
-                                    for d in &mut time_datas {
-                                        d.direction = stacking_direction;
-                                    }
-                                    stacking_direction = match stacking_direction {
-                                        Direction::Horizontal => Direction::Vertical,
-                                        Direction::Vertical => Direction::Horizontal,
-                                    };
-                                    terminal.draw(|frame| {
-                                                draw(frame, &mut time_datas, curid);
-                                            });
-                                }
-
-                                KeyCode::Down => {
-                                    curid += 1;
-                                    if curid > time_datas.len() - 1 {
-                                        curid = 0;
-                                    }
-                                    terminal.draw(|frame| {
-                                                draw(frame, &mut time_datas, curid);
-                                            });
-                                }
-                                KeyCode::Up => {
-                                    if curid == 0 {
-                                        curid = time_datas.len() - 1;
-                                    } else {
-                                        curid -= 1;
-                                    }
-                                    terminal.draw(|frame| {
-                                                draw(frame, &mut time_datas, curid);
-                                            });
-                                }
-                                _ => {}
+                            if key.code == KeyCode::Char('q') {
+                                return;
+                            }
+                            if view.handle(key.code, engine, &snapshots) {
+                                terminal.draw(|frame| view.draw(frame, &snapshots, engine.rejected()));
                             }
                         }
                     }
                 }
             }
-            // self.maybe_report(&mut time_datas, &mut terminal);
-            terminal.draw(|frame| {
-                        draw(frame, &mut time_datas, curid);
-                    });
+            snapshots = engine.snapshots();
+            view.update(&snapshots);
+            terminal.draw(|frame| view.draw(frame, &snapshots, engine.rejected()));
+            let corrected: Vec<TimerSnapshot> = snapshots.iter().map(TimerSnapshot::corrected).collect();
+            publish(sinks, metrics.as_ref(), &corrected);
         }
     }
 }
-fn handle_latency_messages(time_datas: &mut Vec<TimerData>,
-                           readers: &mut Vec<Consumer<'_, TimingMessage>>,
-                           n_samples: usize) {
-    let mut msg = Default::default();
-    for (d, r) in time_datas.iter_mut().zip(readers) {
-        let mut n = 0;
-        while n < n_samples {
-            match r.try_consume(&mut msg) {
-                Ok(()) => {
-                    if d.track_latency(&msg) {
-                        n += 1;
-                    };
-                }
-                Err(ReadError::Empty) => break,
-                Err(ReadError::SpedPast) => r.recover_after_error_dumb(),
-            }
+
+fn publish(sinks: &mut [SnapshotSink], metrics: Option<&MetricsServer>, snapshots: &[TimerSnapshot]) {
+    for sink in sinks {
+        if let Err(e) = sink.write(snapshots) {
+            log::error!("couldn't write snapshots: {e}");
         }
     }
-}
-fn handle_business_messages(time_datas: &mut Vec<TimerData>,
-                            readers: &mut Vec<Consumer<'_, TimingMessage>>,
-                            n_samples: usize) {
-    let mut msg = Default::default();
-    for (d, r) in time_datas.iter_mut().zip(readers) {
-        let mut n = 0;
-        while n < n_samples {
-            match r.try_consume(&mut msg) {
-                Ok(()) => {
-                    if d.track_business(&msg) {
-                        n += 1;
-                    };
-                }
-                Err(ReadError::Empty) => break,
-                Err(ReadError::SpedPast) => r.recover_after_error_dumb(),
-            }
-        }
+    if let Some(metrics) = metrics {
+        metrics.update(snapshots);
     }
 }
 
-fn draw(frame: &mut Frame, time_datas: &mut Vec<TimerData>, curid: usize) {
-    let layout = Layout::default().direction(Direction::Horizontal)
-                                  .constraints([Constraint::Percentage(20), Constraint::Percentage(80)])
-                                  .split(frame.size());
-
-    let namelist: Text = Vec::from_iter(time_datas.iter().enumerate().map(|(i, d)| {
-                                                                         if i == curid {
-                                                                             Span::styled(d.name.clone(),
-                                                                         Style::default().bg(Color::Gray)).into()
-                                                                         } else {
-                                                                             Span::raw(d.name.clone()).into()
-                                                                         }
-                                                                     })).into();
-
-    frame.render_widget(Paragraph::new(namelist).block(Block::new().title("Timers").borders(Borders::ALL)), layout[0]);
-    if let Some(time_data) = time_datas.get_mut(curid) {
-        time_data.report(frame, layout[1]);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_of_names() {
+        let timers = ["gateway.binance.publish", "risk", "gateway.binance.decode", "gateway.okx"];
+        let groups = ["gateway", "gateway.binance"];
+        let mut collapsed = HashSet::new();
+        assert_eq!(tree(&timers, &groups, 1, &collapsed),
+                   [(0, Row::Group(0)),
+                    (1, Row::Group(1)),
+                    (2, Row::Timer(2)),
+                    (2, Row::Timer(0)),
+                    (1, Row::Timer(3)),
+                    (0, Row::Timer(1)),
+                    (0, Row::Pipeline(0))]);
+
+        collapsed.insert("gateway.binance".to_string());
+        assert_eq!(tree(&timers, &groups, 0, &collapsed),
+                   [(0, Row::Group(0)), (1, Row::Group(1)), (1, Row::Timer(3)), (0, Row::Timer(1))]);
     }
 }
@@ -0,0 +1,291 @@
+use std::{fmt::Display, marker::PhantomData, sync::OnceLock};
+
+use ma_time::{Duration, Instant};
+
+use crate::{
+    header::{HeaderKind, Overhead, QueueHeader},
+    lifecycle, messages,
+    payload::{self, Layout, Payload},
+    perf::Perf,
+    sampling, Sampling, QUEUE_DIR, QUEUE_SIZE,
+};
+
+/// Measures durations and latencies, sending them to the timekeeper.
+///
+/// Each message carries the last value passed to [`set_payload`](Timer::set_payload),
+/// see [`payload`](crate::payload).
+#[repr(C)]
+pub struct Timer<P: Payload = ()> {
+    pub curmsg: messages::TimingMessage,
+    timing_producer: ma_queues::Producer<'static, messages::TimingMessage>,
+    latency_producer: ma_queues::Producer<'static, messages::TimingMessage>,
+    business_seq: u64,
+    latency_seq: u64,
+    sampler: sampling::Sampler,
+    latency_sampler: sampling::Sampler,
+    name: String,
+    unlink_on_drop: bool,
+    outlier_threshold: Duration,
+    outlier_producer: Option<ma_queues::Producer<'static, messages::OutlierMessage>>,
+    tag: u64,
+    perf: Option<Box<Perf>>,
+    _payload: PhantomData<P>,
+}
+
+impl Timer {
+    pub fn new<S: Display>(name: S) -> Self {
+        Self::with_payload(name, ())
+    }
+}
+
+impl<P: Payload> Timer<P> {
+    /// A timer whose messages carry a `P`, starting out as `payload`.
+    /// `name` can't contain `#`, see [`SharedTimer`](crate::SharedTimer).
+    pub fn with_payload<S: Display>(name: S, payload: P) -> Self {
+        let name = name.to_string();
+        lifecycle::check_name(&name);
+        Self::with_queue_name(name, payload)
+    }
+
+    /// Like [`with_payload`](Self::with_payload), for the per-thread producers of the registry
+    pub(crate) fn with_queue_name(name: String, payload: P) -> Self {
+        let _ = std::fs::create_dir(QUEUE_DIR);
+        let timing_queue = ma_queues::Queue::shared(
+            format!("{QUEUE_DIR}/timing-{name}"),
+            QUEUE_SIZE,
+            ma_queues::QueueType::SPMC,
+        )
+        .expect("couldn't open timing queue");
+        let latency_queue = ma_queues::Queue::shared(
+            format!("{QUEUE_DIR}/latency-{name}"),
+            QUEUE_SIZE,
+            ma_queues::QueueType::SPMC,
+        )
+        .expect("couldn't open latency queue");
+        lifecycle::write_pid(&name);
+        QueueHeader::write(&name, HeaderKind::Timer, overhead(), &Layout::of::<P>());
+
+        let mut timer = Timer {
+            curmsg: messages::TimingMessage { weight: 1, ..Default::default() },
+            timing_producer: ma_queues::Producer::from(timing_queue),
+            latency_producer: ma_queues::Producer::from(latency_queue),
+            business_seq: 0,
+            latency_seq: 0,
+            sampler: Default::default(),
+            latency_sampler: Default::default(),
+            name,
+            unlink_on_drop: false,
+            outlier_threshold: Duration::MAX,
+            outlier_producer: None,
+            tag: 0,
+            perf: None,
+            _payload: PhantomData,
+        };
+        timer.set_payload(payload);
+        timer
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Removes the queues from [`QUEUE_DIR`] when this timer is dropped,
+    /// rather than leaving them around for [`gc`](crate::gc) to clean up.
+    pub fn set_unlink_on_drop(&mut self, unlink: bool) {
+        self.unlink_on_drop = unlink;
+    }
+
+    /// Only measure and send the calls selected by `sampling`.
+    /// Calls that are not sampled skip reading the clock in `stop`.
+    ///
+    /// [`latency`](Self::latency) samples its calls separately, so mixing it with `start` doesn't
+    /// skew the weights of either. [`record`](Self::record) stands for a whole `start`/`stop` call
+    /// and shares the sampling of `start`.
+    pub fn set_sampling(&mut self, sampling: Sampling) {
+        self.sampler = sampling::Sampler::new(sampling);
+        self.latency_sampler = sampling::Sampler::new(sampling);
+    }
+
+    /// Also sends messages that take longer than `threshold` to the outlier queue,
+    /// so the timekeeper can show them individually rather than averaged into a datapoint.
+    pub fn set_outlier_threshold(&mut self, threshold: Duration) {
+        if self.outlier_producer.is_none() {
+            let queue = ma_queues::Queue::shared(
+                format!("{QUEUE_DIR}/outliers-{}", self.name),
+                QUEUE_SIZE,
+                ma_queues::QueueType::SPMC,
+            )
+            .expect("couldn't open outlier queue");
+            self.outlier_producer = Some(ma_queues::Producer::from(queue));
+        }
+        self.outlier_threshold = threshold;
+    }
+
+    /// Also reads context switches, page faults and, if available, instructions and cycles
+    /// in `start` and `stop`, which the timekeeper shows next to the business timings.
+    /// The counters are those of the calling thread, so call this on the thread using the timer.
+    /// Each read is a syscall, which is not part of the measured time.
+    #[cfg(feature = "perf")]
+    pub fn enable_perf_counters(&mut self) -> std::io::Result<()> {
+        self.perf = Some(Box::new(Perf::new(&self.name)?));
+        Ok(())
+    }
+
+    /// Tag identifying the current call in the outliers it produces, e.g. an order id.
+    pub fn set_tag(&mut self, tag: u64) {
+        self.tag = tag;
+    }
+
+    /// Attaches `payload` to all messages sent from now on.
+    #[inline(always)]
+    pub fn set_payload(&mut self, payload: P) {
+        payload::write(&mut self.curmsg, payload);
+    }
+}
+
+impl<P: Payload> Drop for Timer<P> {
+    fn drop(&mut self) {
+        if self.unlink_on_drop {
+            lifecycle::unlink(&self.name);
+        }
+    }
+}
+
+unsafe impl<P: Payload> Send for Timer<P> {}
+unsafe impl<P: Payload> Sync for Timer<P> {}
+
+/// What `start` does, shared with [`overhead`] so it measures the same instructions
+#[inline(always)]
+fn start_call(sampler: &mut sampling::Sampler, perf: &mut Option<Box<Perf>>, msg: &mut messages::TimingMessage) {
+    let Some(weight) = sampler.sample() else {
+        return;
+    };
+    msg.weight = weight;
+    match (perf, sampler.sampled_at()) {
+        (Some(perf), _) => {
+            perf.start();
+            msg.start_now();
+        }
+        // The sampler already read the clock to decide
+        (None, Some(sampled_at)) => msg.start_at(sampled_at),
+        (None, None) => msg.start_now(),
+    }
+}
+
+/// What `stop` does before sending, returns whether the call was sampled
+#[inline(always)]
+fn stop_call(
+    sampler: &sampling::Sampler,
+    perf: &mut Option<Box<Perf>>,
+    seq: u64,
+    msg: &mut messages::TimingMessage,
+) -> bool {
+    if !sampler.sampled {
+        return false;
+    }
+    msg.stop_now();
+    if let Some(perf) = perf {
+        perf.stop(seq);
+    }
+    true
+}
+
+/// What an empty `start`/`stop` pair of a timer without perf counters takes,
+/// measured once per process when the first timer is created
+pub(crate) fn overhead() -> Overhead {
+    static OVERHEAD: OnceLock<Overhead> = OnceLock::new();
+    *OVERHEAD.get_or_init(|| {
+        let mut sampler = sampling::Sampler::default();
+        let mut perf = None;
+        Overhead::measure(|msg| {
+            start_call(&mut sampler, &mut perf, msg);
+            stop_call(&sampler, &mut perf, 0, msg);
+        })
+    })
+}
+
+impl<P: Payload> Timer<P> {
+    #[inline(always)]
+    pub fn start(&mut self) {
+        start_call(&mut self.sampler, &mut self.perf, &mut self.curmsg);
+    }
+    pub fn start_t(&self) -> &Instant {
+        &self.curmsg.start_t
+    }
+    pub fn stop_t(&self) -> &Instant {
+        &self.curmsg.stop_t
+    }
+    #[inline(always)]
+    pub fn stop(&mut self) {
+        if stop_call(&self.sampler, &mut self.perf, self.business_seq, &mut self.curmsg) {
+            self.send_business();
+        }
+    }
+    pub fn stop_and_latency(&mut self, ingestion_t: Instant) {
+        if !self.sampler.sampled {
+            return;
+        }
+        self.stop();
+        self.set_start(ingestion_t);
+        self.send_latency();
+    }
+    pub fn set_stop(&mut self, stop: Instant) {
+        self.curmsg.stop_t = stop;
+    }
+    pub fn set_start(&mut self, start: Instant) {
+        self.curmsg.start_t = start;
+    }
+
+    /// Sends the latency since `ingestion_t`, sampled separately from `start`, see [`set_sampling`](Self::set_sampling).
+    pub fn latency(&mut self, ingestion_t: Instant) {
+        let Some(weight) = self.latency_sampler.sample() else {
+            return;
+        };
+        self.curmsg.weight = weight;
+        self.set_stop(Instant::now());
+        self.set_start(ingestion_t);
+        self.send_latency();
+    }
+
+    /// Sends one call that was measured elsewhere, e.g. by a [`TimedFuture`](crate::future::TimedFuture):
+    /// the latency since `ingestion_t` and `busy` as the business time, both ending now.
+    /// Subject to sampling like `start`.
+    pub fn record(&mut self, ingestion_t: Instant, busy: Duration) {
+        let Some(weight) = self.sampler.sample() else {
+            return;
+        };
+        self.curmsg.weight = weight;
+        let now = Instant::now();
+        self.set_stop(now);
+        self.set_start(Instant(now.0.saturating_sub(busy.0)));
+        self.send_business();
+        self.set_start(ingestion_t);
+        self.send_latency();
+    }
+    pub fn send_latency(&mut self) {
+        self.curmsg.seq = self.latency_seq;
+        self.latency_seq += 1;
+        self.latency_producer
+            .produce(&self.curmsg);
+        self.maybe_send_outlier(true);
+    }
+
+    pub fn send_business(&mut self) {
+        self.curmsg.seq = self.business_seq;
+        self.business_seq += 1;
+        self.timing_producer
+            .produce(&self.curmsg);
+        self.maybe_send_outlier(false);
+    }
+
+    #[inline(always)]
+    fn maybe_send_outlier(&mut self, latency: bool) {
+        if self.curmsg.elapsed() <= self.outlier_threshold {
+            return;
+        }
+        if let Some(producer) = &mut self.outlier_producer {
+            let (_, cpu) = Instant::now_and_cpu();
+            producer.produce(&messages::OutlierMessage { msg: self.curmsg, tag: self.tag, cpu, latency });
+        }
+    }
+}
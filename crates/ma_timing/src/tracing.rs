@@ -0,0 +1,164 @@
+//! A [`Layer`] that times `tracing` spans, so the timekeeper shows them without
+//! instrumenting the code again.
+//!
+//! Each span is reported to the thread local [`registry`] timer named after it when it
+//! closes: the time from its creation as latency, and the time it spent entered as
+//! business time. Spans with a `#` in their name aren't timed, timer names can't contain it.
+//!
+//! ```no_run
+//! use tracing_subscriber::prelude::*;
+//!
+//! tracing_subscriber::registry().with(ma_timing::tracing::TimingLayer::new()
+//!                                         .with_span_filter(|meta| meta.target().starts_with("gateway")))
+//!                               .init();
+//! ```
+use std::sync::Arc;
+
+use ::tracing::{span, Metadata, Subscriber};
+use ma_time::{Duration, Instant};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::registry;
+
+type SpanFilter = dyn Fn(&Metadata<'_>) -> bool + Send + Sync;
+
+/// Stored in the extensions of the spans that are timed
+struct SpanTimes {
+    created: Instant,
+    /// When the outermost of the current entries started
+    entered: Instant,
+    /// Entries not exited yet, a span can be re-entered or entered on several threads at once
+    depth:   usize,
+    busy:    Duration,
+}
+
+impl SpanTimes {
+    fn new() -> Self {
+        Self { created: Instant::now(), entered: Instant::ZERO, depth: 0, busy: Duration::ZERO }
+    }
+
+    fn enter(&mut self) {
+        if self.depth == 0 {
+            self.entered = Instant::now();
+        }
+        self.depth += 1;
+    }
+
+    /// Only the outermost exit adds to `busy`, so overlapping entries are counted once
+    fn exit(&mut self) {
+        let Some(depth) = self.depth.checked_sub(1) else {
+            return;
+        };
+        self.depth = depth;
+        if depth == 0 {
+            self.busy += Duration::elapsed(self.entered);
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TimingLayer {
+    filter: Option<Arc<SpanFilter>>,
+}
+
+impl TimingLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only time the spans for which `filter` returns true.
+    pub fn with_span_filter<F>(mut self, filter: F) -> Self
+        where F: Fn(&Metadata<'_>) -> bool + Send + Sync + 'static
+    {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    fn times(&self, metadata: &Metadata<'_>) -> bool {
+        match &self.filter {
+            Some(filter) => filter(metadata),
+            None => true,
+        }
+    }
+}
+
+impl<S> Layer<S> for TimingLayer where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if span.name().contains('#') || !self.times(span.metadata()) {
+            return;
+        }
+        span.extensions_mut().insert(SpanTimes::new());
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(times) = extensions.get_mut::<SpanTimes>() {
+            times.enter();
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(times) = extensions.get_mut::<SpanTimes>() {
+            times.exit();
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let times = span.extensions_mut().remove::<SpanTimes>();
+        if let Some(times) = times {
+            registry::local(span.name()).record(times.created, times.busy);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn filtered_spans_become_timers() {
+        let layer = TimingLayer::new().with_span_filter(|meta| meta.name() == "tracing-test-timed");
+        ::tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            ::tracing::info_span!("tracing-test-timed").in_scope(|| {});
+            ::tracing::info_span!("tracing-test-skipped").in_scope(|| {});
+        });
+        let exists = |name: &str| {
+            let queue_name = registry::thread_queue_name(name, 0);
+            std::path::Path::new(&format!("{}/latency-{queue_name}", crate::QUEUE_DIR)).exists()
+        };
+        assert!(exists("tracing-test-timed"));
+        assert!(!exists("tracing-test-skipped"));
+        crate::lifecycle::unlink(&registry::thread_queue_name("tracing-test-timed", 0));
+    }
+
+    #[test]
+    fn overlapping_entries_count_once() {
+        let mut times = SpanTimes::new();
+        times.enter();
+        times.enter();
+        times.exit();
+        assert_eq!(times.busy, Duration::ZERO);
+        times.exit();
+        let busy = times.busy;
+        assert!(busy > Duration::ZERO);
+        // An exit without an entry doesn't add anything
+        times.exit();
+        assert_eq!(times.busy, busy);
+    }
+}
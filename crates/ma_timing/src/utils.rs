@@ -134,6 +134,32 @@ impl<'a, T> Iterator for IterMut<'a, T> where T: 'a
     }
 }
 
+/// Formats nanos since the unix epoch as local time with a chrono `format`
+pub fn format_time(t: ma_time::Nanos, format: &str) -> String {
+    let (secs, nanos) = ((t.0 / 1_000_000_000) as i64, (t.0 % 1_000_000_000) as u32);
+    chrono::DateTime::from_timestamp(secs, nanos).map(|t| t.with_timezone(&chrono::Local).format(format).to_string())
+                                                 .unwrap_or_default()
+}
+
+/// The bytes of `v`, which has to be `#[repr(C)]` without padding to be written and read back as raw bytes
+pub(crate) fn bytes_of<T: Copy>(v: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(v as *const T as *const u8, std::mem::size_of::<T>()) }
+}
+
+/// `s` truncated to fit, followed by nuls
+pub(crate) fn nul_padded<const N: usize>(s: &str) -> [u8; N] {
+    let mut bytes = [0; N];
+    let n = s.len().min(N - 1);
+    bytes[..n].copy_from_slice(&s.as_bytes()[..n]);
+    bytes
+}
+
+/// Inverse of [`nul_padded`]
+pub(crate) fn until_nul(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..len]).unwrap_or("?")
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -160,4 +186,15 @@ mod tests {
         }
         assert_eq!(tot, buf.iter().sum::<i32>());
     }
+
+    #[test]
+    fn raw_bytes() {
+        // Headers written with `bytes_of` can't have padding
+        assert_eq!(std::mem::size_of::<crate::header::QueueHeader>(), 672);
+        assert_eq!(std::mem::size_of::<crate::recorder::CaptureHeader>(), 112);
+
+        let name: [u8; 8] = nul_padded("gateway.decode");
+        assert_eq!(until_nul(&name), "gateway");
+        assert_eq!(until_nul(&nul_padded::<8>("")), "");
+    }
 }
@@ -0,0 +1,46 @@
+#![cfg(feature = "disabled")]
+
+use std::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use ma_time::Instant;
+use ma_timing::future::{TimedExt, TimedFuture};
+
+#[test]
+fn no_shm_files() {
+    let name = format!("disabled-test-{}", std::process::id());
+
+    let mut timer = ma_timing::Timer::new(&name);
+    timer.start();
+    timer.stop_and_latency(Instant::now());
+    timer.latency(Instant::now());
+
+    let mut pipeline = ma_timing::PipelineTimer::new(&name, &["decode", "send"]);
+    pipeline.start();
+    pipeline.checkpoint(0);
+    pipeline.checkpoint(1);
+    pipeline.send();
+
+    let shared = ma_timing::SharedTimer::new(&name);
+    shared.start();
+    shared.stop();
+    let mut cx = Context::from_waker(Waker::noop());
+    let timed = pin!(std::future::ready(1).timed(&shared));
+    assert_eq!(timed.poll(&mut cx), Poll::Ready(1));
+    let timed = pin!(TimedFuture::new(std::future::ready(2), shared.clone()));
+    assert_eq!(timed.poll(&mut cx), Poll::Ready(2));
+
+    ma_timing::timed!("disabled-test-macro", {
+        ma_timing::timer!("disabled-test-macro").latency(Instant::now())
+    });
+
+    for entry in std::fs::read_dir(ma_timing::lifecycle::queue_dir()).unwrap().filter_map(|e| e.ok()) {
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        assert!(!file_name.contains(&name) && !file_name.contains("disabled-test-macro"),
+                "{file_name} was created");
+    }
+}